@@ -0,0 +1,120 @@
+use std::net::IpAddr;
+
+/// A block of addresses in CIDR notation, e.g. `192.168.0.0/16`.
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl IpRange {
+    /// Parses the `ADDRESS/PREFIX` value given to `--allow`. A bare address
+    /// stands for just itself.
+    pub fn parse(range: &str) -> Result<IpRange, String> {
+        let invalid = || format!("Invalid IP range, expected ADDRESS/PREFIX: {}", range);
+        let (address, prefix_len) = match range.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (range, None),
+        };
+
+        let network: IpAddr = address.parse().map_err(|_| invalid())?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) if prefix_len.bytes().all(|b| b.is_ascii_digit()) => {
+                prefix_len.parse().map_err(|_| invalid())?
+            }
+            Some(_) => return Err(invalid()),
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+        // Clients are matched by their IPv4 address when they have one, so
+        // a range of IPv4-mapped addresses has to be written the same way
+        if let (IpAddr::V4(ipv4), true) = (network.to_canonical(), network.is_ipv6()) {
+            if prefix_len >= 96 {
+                return Ok(IpRange {
+                    network: IpAddr::V4(ipv4),
+                    prefix_len: prefix_len - 96,
+                });
+            }
+        }
+        Ok(IpRange {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// Whether `ip` falls inside the range. IPv4 clients reaching an IPv6
+    /// socket are matched by their IPv4 address.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(range: &str, ip: &str) -> bool {
+        IpRange::parse(range).unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn ipv4_ranges() {
+        assert!(contains("192.168.0.0/16", "192.168.40.2"));
+        assert!(!contains("192.168.0.0/16", "192.169.0.1"));
+        assert!(contains("10.1.2.3/8", "10.200.0.1"));
+        assert!(contains("10.0.0.1/32", "10.0.0.1"));
+        assert!(!contains("10.0.0.1/32", "10.0.0.2"));
+        assert!(contains("10.0.0.1", "10.0.0.1"));
+        assert!(!contains("10.0.0.1", "10.0.0.2"));
+        assert!(contains("0.0.0.0/0", "203.0.113.9"));
+        assert!(!contains("0.0.0.0/0", "::1"));
+    }
+
+    #[test]
+    fn ipv6_ranges() {
+        assert!(contains("2001:db8::/32", "2001:db8:1::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+        assert!(contains("::1", "::1"));
+        assert!(!contains("::1/128", "::2"));
+        assert!(contains("::/0", "fe80::1"));
+        assert!(!contains("::/0", "127.0.0.1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_as_ipv4() {
+        assert!(contains("127.0.0.0/8", "::ffff:127.0.0.1"));
+        assert!(!contains("127.0.0.0/8", "::ffff:10.0.0.1"));
+        assert!(contains("::ffff:10.0.0.0/104", "10.1.2.3"));
+        assert!(contains("::ffff:10.0.0.0/104", "::ffff:10.1.2.3"));
+        assert!(!contains("::ffff:10.0.0.0/104", "11.0.0.1"));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        for range in [
+            "",
+            "10.0.0.0/",
+            "10.0.0.0/33",
+            "10.0.0.0/+8",
+            "10.0.0.0/-1",
+            "10.0.0.0/8/8",
+            "::/129",
+            "example.com/8",
+            "10.0.0/8",
+        ] {
+            assert!(IpRange::parse(range).is_err(), "{}", range);
+        }
+    }
+}
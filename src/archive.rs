@@ -0,0 +1,154 @@
+use crate::config::{Config, Mount};
+use crate::listing;
+use crate::logger;
+use crate::request::Request;
+use crate::response::{self, Response};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+/// How much of an archive is gathered before it's sent as a chunk.
+const ZIP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Whether the client asked for a directory as a ZIP archive.
+pub fn wants_zip(request: &Request) -> bool {
+    request.query_param("download").as_deref() == Some("zip")
+}
+
+/// Streams `dir` and everything below it as a ZIP archive. Entries are
+/// written as the directory is walked, so only one file is read at a time
+/// and the whole archive is never held in memory. Its size isn't known up
+/// front, so it's sent in chunks.
+pub fn serve_zip(
+    dir: &Path,
+    mount: &Mount,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+) {
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "download".to_string());
+
+    let headers = format!(
+        "Content-Type: application/zip\r\n{}",
+        response::content_disposition("attachment", &format!("{}.zip", name))
+    );
+    if response.write_chunked_head(200, &headers).is_err() || request.is_head() {
+        return;
+    }
+
+    match write_zip(dir, mount, config, response).and_then(|_| response.finish()) {
+        Err(e) if !response::is_disconnect(&e) => logger::error(&format!(
+            "Failed to send {} as a ZIP archive: {}",
+            dir.display(),
+            e
+        )),
+        _ => {}
+    }
+}
+
+fn write_zip(
+    dir: &Path,
+    mount: &Mount,
+    config: &Config,
+    response: &mut Response,
+) -> io::Result<()> {
+    // Buffered so the archive's many small writes don't each become a chunk
+    let mut zip = ZipWriter::new_stream(BufWriter::with_capacity(
+        ZIP_BUFFER_SIZE,
+        Sink {
+            response,
+            failed: false,
+        },
+    ));
+
+    let entries = WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !listing::is_hidden(entry, mount, config));
+    for entry in entries {
+        // Skip anything that can't be read rather than failing the download
+        let Ok(entry) = entry else { continue };
+        let relative_path = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        let name = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let metadata = entry.metadata().ok();
+        let mut options = SimpleFileOptions::default();
+        if let Some(modified) = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok())
+        {
+            if let Some(modified) = zip_time(modified) {
+                options = options.last_modified_time(modified);
+            }
+        }
+
+        if entry.file_type().is_dir() {
+            zip.add_directory(name, options)?;
+        } else if entry.file_type().is_file() {
+            let Ok(mut file) = File::open(entry.path()) else {
+                continue;
+            };
+            let size = metadata.map_or(0, |metadata| metadata.len());
+            let options = options
+                .compression_method(CompressionMethod::Deflated)
+                .large_file(size >= u32::MAX as u64);
+            zip.start_file(name, options)?;
+            io::copy(&mut file, &mut zip)?;
+        }
+    }
+
+    zip.finish()?.flush()
+}
+
+/// Passes the archive through to the response until a write fails. After
+/// that writes are dropped, so the writer finishing the archive when it's
+/// dropped doesn't complain about the client having gone.
+struct Sink<'a, 'b> {
+    response: &'a mut Response<'b>,
+    failed: bool,
+}
+
+impl Write for Sink<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.failed {
+            return Ok(buf.len());
+        }
+        let result = self.response.write(buf);
+        self.failed = result.is_err();
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.failed {
+            return Ok(());
+        }
+        self.response.flush()
+    }
+}
+
+/// Converts a timestamp to the archive's format, which can't represent
+/// anything before 1980.
+fn zip_time(time: SystemTime) -> Option<DateTime> {
+    let (year, month, day, hour, minute, second) = listing::utc_fields(time);
+    DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month as u8,
+        day as u8,
+        hour as u8,
+        minute as u8,
+        second as u8,
+    )
+    .ok()
+}
@@ -0,0 +1,45 @@
+use crate::request::Request;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Credentials required through HTTP Basic authentication.
+pub struct BasicAuth {
+    /// The base64 form of `user:password`, as it appears in the header.
+    expected: String,
+}
+
+impl BasicAuth {
+    /// Parses the `user:password` value given to `--auth`.
+    pub fn parse(credentials: &str) -> Result<BasicAuth, String> {
+        match credentials.split_once(':') {
+            Some((user, _)) if !user.is_empty() => Ok(BasicAuth {
+                expected: STANDARD.encode(credentials),
+            }),
+            _ => Err(format!(
+                "Invalid credentials, expected USER:PASSWORD: {}",
+                credentials
+            )),
+        }
+    }
+
+    /// Whether the request's `Authorization` header carries the credentials.
+    pub fn is_authorized(&self, request: &Request) -> bool {
+        let token = match request
+            .header("Authorization")
+            .and_then(|value| value.split_once(' '))
+        {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("Basic") => token.trim(),
+            _ => return false,
+        };
+        constant_time_eq(token.as_bytes(), self.expected.as_bytes())
+    }
+}
+
+/// Compares two byte strings in time that depends only on their lengths, so
+/// the response time doesn't reveal how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
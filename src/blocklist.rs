@@ -0,0 +1,75 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+/// Paths that commonly hold credentials, in `.gitignore` syntax. Serving a
+/// home directory would otherwise hand these to anyone who asks.
+pub const PATTERNS: &[&str] = &[
+    ".ssh/",
+    ".gnupg/",
+    ".aws/",
+    ".azure/",
+    ".kube/",
+    ".docker/config.json",
+    ".git-credentials",
+    ".netrc",
+    ".npmrc",
+    ".pypirc",
+    ".pgpass",
+    ".htpasswd",
+    ".env",
+    ".env.*",
+    ".bash_history",
+    ".zsh_history",
+    "id_rsa",
+    "id_dsa",
+    "id_ecdsa",
+    "id_ed25519",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "*.kdbx",
+];
+
+/// Sensitive paths that are refused, and left out of listings and archives,
+/// whatever `--show-hidden` and the ignore files say.
+pub struct Blocklist {
+    matcher: Gitignore,
+}
+
+impl Blocklist {
+    /// Builds the blocklist from the built-in patterns, leaving out those
+    /// given to `--unblock`, which must be among them.
+    pub fn new(unblocked: &[String]) -> Result<Blocklist, String> {
+        if let Some(unknown) = unblocked
+            .iter()
+            .find(|pattern| !PATTERNS.contains(&pattern.as_str()))
+        {
+            return Err(format!("Unknown blocklist pattern: {}", unknown));
+        }
+
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in PATTERNS
+            .iter()
+            .filter(|pattern| !unblocked.iter().any(|unblocked| unblocked == *pattern))
+        {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| format!("Invalid blocklist pattern {}: {}", pattern, e))?;
+        }
+        let matcher = builder
+            .build()
+            .map_err(|e| format!("Invalid blocklist: {}", e))?;
+        Ok(Blocklist { matcher })
+    }
+
+    /// Whether `relative_path`, a path inside a mount, or any directory
+    /// above it matches the list.
+    pub fn matches(&self, relative_path: &Path, is_dir: bool) -> bool {
+        !relative_path.as_os_str().is_empty()
+            && self
+                .matcher
+                .matched_path_or_any_parents(relative_path, is_dir)
+                .is_ignore()
+    }
+}
@@ -0,0 +1,483 @@
+use crate::config::Config;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zip::{DateTime, ZipArchive};
+
+/// Tar files are made of 512-byte blocks: a header before each entry, and
+/// the entry's data padded to a whole number of blocks.
+const TAR_BLOCK_SIZE: u64 = 512;
+
+/// A ZIP or tar archive served read-only in place of a directory. Its
+/// entries are read once, when the server starts.
+pub struct Bundle {
+    path: PathBuf,
+    kind: Kind,
+    /// Every file and directory inside, by its path without a leading `/`.
+    /// The archive's root is the empty path.
+    entries: BTreeMap<String, Node>,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Zip,
+    Tar,
+}
+
+/// A file or directory inside an archive.
+pub struct Node {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Where the data is: the entry's index in a ZIP archive, or the offset
+    /// of its first byte in a tar file.
+    start: u64,
+}
+
+impl Node {
+    fn dir(modified: Option<SystemTime>) -> Node {
+        Node {
+            is_dir: true,
+            size: 0,
+            modified,
+            start: 0,
+        }
+    }
+}
+
+/// Whether `path` names an archive that can be served as a directory.
+pub fn is_bundle(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+fn archive_kind(path: &Path) -> Option<Kind> {
+    let extension = path.extension()?.to_str()?;
+    if extension.eq_ignore_ascii_case("zip") {
+        Some(Kind::Zip)
+    } else if extension.eq_ignore_ascii_case("tar") {
+        Some(Kind::Tar)
+    } else {
+        None
+    }
+}
+
+/// Turns a path inside an archive into the form it's looked up by, without
+/// empty or `.` segments. Paths with `..` could mean something outside the
+/// archive, so they give `None`.
+pub fn normalize(path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Whether an archive entry is left out like a dotfile or a file on the
+/// blocklist would be.
+pub fn is_hidden(name: &str, is_dir: bool, config: &Config) -> bool {
+    (!config.show_hidden && name.split('/').any(|segment| segment.starts_with('.')))
+        || config.blocklist.matches(Path::new(name), is_dir)
+}
+
+impl Bundle {
+    /// Reads the list of entries in the archive at `path`.
+    pub fn open(path: &Path) -> Result<Bundle, String> {
+        let kind = archive_kind(path)
+            .ok_or_else(|| format!("Cannot serve {}: not a ZIP or tar archive", path.display()))?;
+        let modified = path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let mut bundle = Bundle {
+            path: path.to_path_buf(),
+            kind,
+            entries: BTreeMap::from([(String::new(), Node::dir(modified))]),
+        };
+
+        let read = match kind {
+            Kind::Zip => bundle.read_zip(),
+            Kind::Tar => bundle.read_tar(),
+        };
+        read.map_err(|e| format!("Cannot serve {}: {}", path.display(), e))?;
+        Ok(bundle)
+    }
+
+    fn read_zip(&mut self) -> io::Result<()> {
+        let archive = ZipArchive::new(File::open(&self.path)?).map_err(io::Error::other)?;
+        for index in 0..archive.len() {
+            let entry = archive.by_index_data(index).map_err(io::Error::other)?;
+            let Ok(name) = entry.name() else {
+                continue;
+            };
+            let node = Node {
+                is_dir: entry.is_dir(),
+                size: entry.size(),
+                modified: entry.last_modified().and_then(system_time),
+                start: index as u64,
+            };
+            self.insert(&name, node);
+        }
+        Ok(())
+    }
+
+    fn read_tar(&mut self) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let len = file.metadata()?.len();
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid tar header");
+        // GNU and pax archives put long names in an entry of their own, which
+        // applies to the entry after it
+        let mut long_name = None;
+        let mut header = [0; TAR_BLOCK_SIZE as usize];
+        loop {
+            match file.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            // The archive ends with blocks of zeros
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+
+            // Binary fields can hold any number, so a crafted header mustn't
+            // be able to overflow the arithmetic below
+            let size = tar_number(&header[124..136]).ok_or_else(invalid)?;
+            let modified = tar_number(&header[136..148])
+                .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)));
+            let start = file.stream_position()?;
+            let end = size
+                .div_ceil(TAR_BLOCK_SIZE)
+                .checked_mul(TAR_BLOCK_SIZE)
+                .and_then(|padded| start.checked_add(padded))
+                .ok_or_else(invalid)?;
+            // An entry claiming more data than the file holds would be
+            // served cut short of its `Content-Length`
+            if start + size > len {
+                return Err(invalid());
+            }
+
+            match header[156] {
+                b'L' | b'x' => {
+                    let mut data = Vec::new();
+                    (&mut file).take(size).read_to_end(&mut data)?;
+                    long_name = if header[156] == b'L' {
+                        Some(tar_string(&data))
+                    } else {
+                        pax_path(&data).or(long_name)
+                    };
+                    file.seek(SeekFrom::Start(end))?;
+                    continue;
+                }
+                kind @ (b'0' | 0 | b'7' | b'5') => {
+                    let name = long_name.take().unwrap_or_else(|| tar_name(&header));
+                    let node = Node {
+                        is_dir: kind == b'5',
+                        size: if kind == b'5' { 0 } else { size },
+                        modified,
+                        start,
+                    };
+                    self.insert(&name, node);
+                }
+                // Links, devices and the like have nothing to serve
+                _ => long_name = None,
+            }
+            file.seek(SeekFrom::Start(end))?;
+        }
+        Ok(())
+    }
+
+    /// Adds an entry, along with any directories above it the archive
+    /// doesn't list itself. Entries that would land outside the archive's
+    /// root are dropped.
+    fn insert(&mut self, name: &str, node: Node) {
+        let Some(name) = normalize(name) else {
+            return;
+        };
+        if name.is_empty() {
+            return;
+        }
+        let mut parent = name.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            self.entries
+                .entry(dir.to_string())
+                .or_insert_with(|| Node::dir(None));
+            parent = dir;
+        }
+        self.entries.insert(name, node);
+    }
+
+    /// Looks up an entry by its normalized path.
+    pub fn get(&self, name: &str) -> Option<&Node> {
+        self.entries.get(name)
+    }
+
+    /// The entries directly inside the directory `dir`, by their full paths.
+    pub fn children<'a>(&'a self, dir: &str) -> impl Iterator<Item = (&'a str, &'a Node)> {
+        let prefix = if dir.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", dir)
+        };
+        let len = prefix.len();
+        self.entries
+            .range(prefix.clone()..)
+            .take_while(move |(name, _)| name.starts_with(&prefix))
+            .filter(move |(name, _)| {
+                let rest = &name[len..];
+                !rest.is_empty() && !rest.contains('/')
+            })
+            .map(|(name, node)| (name.as_str(), node))
+    }
+
+    /// Every file in the archive, at any depth, by its full path.
+    pub fn files(&self) -> impl Iterator<Item = (&str, &Node)> {
+        self.entries
+            .iter()
+            .filter(|(_, node)| !node.is_dir)
+            .map(|(name, node)| (name.as_str(), node))
+    }
+
+    /// Runs `f` with a reader over the decompressed contents of `node`,
+    /// which must be a file.
+    pub fn read<T>(
+        &self,
+        node: &Node,
+        f: impl FnOnce(&mut dyn Read) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut file = File::open(&self.path)?;
+        match self.kind {
+            Kind::Zip => {
+                let mut archive = ZipArchive::new(file).map_err(io::Error::other)?;
+                let mut entry = archive
+                    .by_index(node.start as usize)
+                    .map_err(io::Error::other)?;
+                f(&mut entry)
+            }
+            Kind::Tar => {
+                file.seek(SeekFrom::Start(node.start))?;
+                f(&mut file.take(node.size))
+            }
+        }
+    }
+}
+
+/// Reads a NUL-terminated string from a header field.
+fn tar_string(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Reads a number from a header field: octal digits, or for values too big
+/// for them, big-endian binary marked by the top bit of the first byte.
+fn tar_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|&byte| byte & 0x80 != 0) {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |value, &byte| {
+                value.checked_mul(256).map(|value| value + u64::from(byte))
+            });
+    }
+    let digits = tar_string(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// The entry's name from its header, joined to the prefix field ustar
+/// archives use for long paths.
+fn tar_name(header: &[u8]) -> String {
+    let name = tar_string(&header[0..100]);
+    let prefix = if &header[257..262] == b"ustar" {
+        tar_string(&header[345..500])
+    } else {
+        String::new()
+    };
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// The `path` record of a pax extended header, made of lines like
+/// `30 path=some/long/file/name.txt`.
+fn pax_path(data: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(data).lines().find_map(|line| {
+        let (_, record) = line.split_once(' ')?;
+        record.strip_prefix("path=").map(str::to_string)
+    })
+}
+
+/// Converts a ZIP timestamp, which has no time zone, taking it as UTC.
+fn system_time(time: DateTime) -> Option<SystemTime> {
+    // Days from 1970-01-01 to the date, counting in eras of 400 years that
+    // start on the 1st of March
+    let (month, day) = (i64::from(time.month()), i64::from(time.day()));
+    let year = i64::from(time.year()) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let secs = days * 86_400
+        + i64::from(time.hour()) * 3600
+        + i64::from(time.minute()) * 60
+        + i64::from(time.second());
+    u64::try_from(secs)
+        .ok()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// A tar header block for an entry, with its size in octal.
+    fn header(name: &str, size: u64, kind: u8) -> Vec<u8> {
+        let mut block = vec![0; TAR_BLOCK_SIZE as usize];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        block[136..147].copy_from_slice(b"14000000000");
+        block[156] = kind;
+        block
+    }
+
+    /// An entry's header followed by its data, padded to whole blocks.
+    fn entry(name: &str, data: &[u8], kind: u8) -> Vec<u8> {
+        let mut bytes = header(name, data.len() as u64, kind);
+        bytes.extend_from_slice(data);
+        bytes.resize(bytes.len().next_multiple_of(TAR_BLOCK_SIZE as usize), 0);
+        bytes
+    }
+
+    fn open_tar(dir: &TempDir, blocks: &[Vec<u8>]) -> Result<Bundle, String> {
+        let path = dir.path().join("test.tar");
+        let mut bytes = blocks.concat();
+        bytes.extend_from_slice(&[0; 2 * TAR_BLOCK_SIZE as usize]);
+        std::fs::write(&path, bytes).unwrap();
+        Bundle::open(&path)
+    }
+
+    fn contents(bundle: &Bundle, name: &str) -> String {
+        let node = bundle.get(name).unwrap();
+        bundle
+            .read(node, |reader| {
+                let mut text = String::new();
+                reader.read_to_string(&mut text).map(|_| text)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn octal_numbers() {
+        assert_eq!(tar_number(b"00000001750\0"), Some(1000));
+        assert_eq!(tar_number(b"   1750 \0\0\0\0"), Some(1000));
+        assert_eq!(tar_number(b"\0\0\0\0\0\0\0\0"), Some(0));
+        assert_eq!(tar_number(b"        "), Some(0));
+        assert_eq!(tar_number(b"0000001789\0"), None);
+    }
+
+    #[test]
+    fn binary_numbers() {
+        let mut field = [0; 12];
+        field[0] = 0x80;
+        field[8..].copy_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(tar_number(&field), Some(1 << 24));
+
+        field[4..].copy_from_slice(&[0xff; 8]);
+        assert_eq!(tar_number(&field), Some(u64::MAX));
+        field[3] = 0x01;
+        assert_eq!(tar_number(&field), None);
+    }
+
+    #[test]
+    fn names_join_the_ustar_prefix() {
+        let mut block = header("file.txt", 0, b'0');
+        assert_eq!(tar_name(&block), "file.txt");
+        block[257..262].copy_from_slice(b"ustar");
+        block[345..349].copy_from_slice(b"some");
+        assert_eq!(tar_name(&block), "some/file.txt");
+    }
+
+    #[test]
+    fn pax_headers_give_the_path() {
+        let data = b"20 mtime=1700000000\n30 path=some/long/name.txt\n";
+        assert_eq!(pax_path(data), Some("some/long/name.txt".to_string()));
+        assert_eq!(pax_path(b"20 mtime=1700000000\n"), None);
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize("a/b/c"), Some("a/b/c".to_string()));
+        assert_eq!(normalize("/./a//b/"), Some("a/b".to_string()));
+        assert_eq!(normalize(""), Some(String::new()));
+        assert_eq!(normalize("a/../b"), None);
+        assert_eq!(normalize("../etc/passwd"), None);
+        assert_eq!(normalize("a/..b"), Some("a/..b".to_string()));
+    }
+
+    #[test]
+    fn tar_entries_are_listed() {
+        let dir = TempDir::new("tar-entries");
+        let long_name = format!("{}/long.txt", "d".repeat(120));
+        let bundle = open_tar(
+            &dir,
+            &[
+                entry("docs/", b"", b'5'),
+                entry("docs/readme.txt", b"hello", b'0'),
+                entry("deep/er/file.txt", b"deeper", 0),
+                entry("././@LongLink", long_name.as_bytes(), b'L'),
+                entry("truncated", b"long", b'0'),
+                entry("link", b"", b'2'),
+                entry("../escape.txt", b"out", b'0'),
+            ],
+        )
+        .unwrap();
+
+        let names: Vec<&str> = bundle.children("").map(|(name, _)| name).collect();
+        assert_eq!(names, ["d".repeat(120).as_str(), "deep", "docs"]);
+        assert!(bundle.get("deep/er").unwrap().is_dir);
+        assert!(bundle.get("link").is_none());
+        assert!(bundle.get("escape.txt").is_none());
+        assert_eq!(bundle.get("docs/readme.txt").unwrap().size, 5);
+        assert_eq!(
+            bundle.get("docs/readme.txt").unwrap().modified,
+            Some(UNIX_EPOCH + Duration::from_secs(0o14000000000))
+        );
+        assert_eq!(contents(&bundle, "docs/readme.txt"), "hello");
+        assert_eq!(contents(&bundle, "deep/er/file.txt"), "deeper");
+        assert_eq!(contents(&bundle, &long_name), "long");
+    }
+
+    #[test]
+    fn tar_sizes_that_overflow_are_rejected() {
+        let dir = TempDir::new("tar-overflow");
+        let mut block = header("huge.bin", 0, b'0');
+        block[124] = 0x80;
+        block[128..136].copy_from_slice(&[0xff; 8]);
+        assert!(open_tar(&dir, &[block]).is_err());
+    }
+
+    #[test]
+    fn tar_entries_past_the_end_of_the_file_are_rejected() {
+        let dir = TempDir::new("tar-short");
+        let path = dir.path().join("short.tar");
+        let mut bytes = header("short.txt", 4096, b'0');
+        bytes.extend_from_slice(b"only this much");
+        std::fs::write(&path, bytes).unwrap();
+        assert!(Bundle::open(&path).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Largest file kept in the cache. Bigger files are always streamed from
+/// disk.
+pub const MAX_ENTRY_SIZE: u64 = 1024 * 1024;
+
+/// A bounded in-memory cache of file contents, evicting the least recently
+/// used files once it's full.
+pub struct FileCache {
+    capacity: u64,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<PathBuf, Entry>,
+    /// Paths by when they were last used, oldest first.
+    recency: BTreeMap<u64, PathBuf>,
+    used: u64,
+    clock: u64,
+}
+
+struct Entry {
+    bytes: Arc<[u8]>,
+    modified: SystemTime,
+    last_used: u64,
+}
+
+impl FileCache {
+    /// Creates a cache holding at most `capacity` bytes of file contents.
+    pub fn new(capacity: u64) -> FileCache {
+        FileCache {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Returns the cached contents of `path`, if they're still current for a
+    /// file last modified at `modified` with length `len`. Stale contents
+    /// are dropped.
+    pub fn get(&self, path: &Path, modified: SystemTime, len: u64) -> Option<Arc<[u8]>> {
+        let mut inner = self.inner.lock().ok()?;
+        let entry = inner.entries.get(path)?;
+        if entry.modified != modified || entry.bytes.len() as u64 != len {
+            inner.remove(path);
+            return None;
+        }
+
+        let bytes = Arc::clone(&entry.bytes);
+        inner.touch(path);
+        Some(bytes)
+    }
+
+    /// Caches `bytes` as the contents of `path` and hands them back for
+    /// serving, making room by evicting the least recently used files.
+    pub fn insert(&self, path: &Path, modified: SystemTime, bytes: Vec<u8>) -> Arc<[u8]> {
+        let bytes: Arc<[u8]> = bytes.into();
+        let size = bytes.len() as u64;
+        if size > self.capacity {
+            return bytes;
+        }
+        let Ok(mut inner) = self.inner.lock() else {
+            return bytes;
+        };
+
+        inner.remove(path);
+        while inner.used + size > self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            inner.remove(&oldest);
+        }
+
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.recency.insert(last_used, path.to_path_buf());
+        inner.used += size;
+        inner.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                bytes: Arc::clone(&bytes),
+                modified,
+                last_used,
+            },
+        );
+        bytes
+    }
+
+    /// Drops the cached contents of `path`, if there are any.
+    pub fn remove(&self, path: &Path) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.remove(path);
+        }
+    }
+}
+
+impl Inner {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.recency.remove(&entry.last_used);
+            self.used -= entry.bytes.len() as u64;
+        }
+    }
+
+    /// Marks `path` as just used.
+    fn touch(&mut self, path: &Path) {
+        self.clock += 1;
+        let now = self.clock;
+        if let Some(entry) = self.entries.get_mut(path) {
+            let previous = std::mem::replace(&mut entry.last_used, now);
+            if let Some(path) = self.recency.remove(&previous) {
+                self.recency.insert(now, path);
+            }
+        }
+    }
+}
+
+/// The contents of a file being served, either read from disk as it's sent
+/// or already in memory.
+pub enum Contents {
+    File(File),
+    Cached(Cursor<Arc<[u8]>>),
+}
+
+impl Read for Contents {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Contents::File(file) => file.read(buf),
+            Contents::Cached(bytes) => bytes.read(buf),
+        }
+    }
+}
+
+impl Seek for Contents {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Contents::File(file) => file.seek(pos),
+            Contents::Cached(bytes) => bytes.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cached(cache: &FileCache, name: &str, len: u64) -> bool {
+        cache
+            .get(Path::new(name), SystemTime::UNIX_EPOCH, len)
+            .is_some()
+    }
+
+    fn insert(cache: &FileCache, name: &str, len: usize) {
+        cache.insert(Path::new(name), SystemTime::UNIX_EPOCH, vec![0; len]);
+    }
+
+    #[test]
+    fn the_least_recently_used_file_is_evicted() {
+        let cache = FileCache::new(300);
+        insert(&cache, "a", 100);
+        insert(&cache, "b", 100);
+        insert(&cache, "c", 100);
+        insert(&cache, "d", 100);
+        assert!(!cached(&cache, "a", 100));
+        assert!(cached(&cache, "b", 100));
+        assert!(cached(&cache, "c", 100));
+        assert!(cached(&cache, "d", 100));
+    }
+
+    #[test]
+    fn reading_a_file_keeps_it_cached() {
+        let cache = FileCache::new(300);
+        insert(&cache, "a", 100);
+        insert(&cache, "b", 100);
+        insert(&cache, "c", 100);
+        assert!(cached(&cache, "a", 100));
+        insert(&cache, "d", 150);
+        assert!(cached(&cache, "a", 100));
+        assert!(!cached(&cache, "b", 100));
+        assert!(!cached(&cache, "c", 100));
+        assert!(cached(&cache, "d", 150));
+    }
+
+    #[test]
+    fn replacing_a_file_frees_its_old_contents() {
+        let cache = FileCache::new(300);
+        insert(&cache, "a", 200);
+        insert(&cache, "a", 250);
+        insert(&cache, "b", 50);
+        assert!(cached(&cache, "a", 250));
+        assert!(cached(&cache, "b", 50));
+    }
+
+    #[test]
+    fn stale_contents_are_dropped() {
+        let cache = FileCache::new(300);
+        insert(&cache, "a", 100);
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        assert!(cache.get(Path::new("a"), later, 100).is_none());
+        assert!(!cached(&cache, "a", 100));
+
+        insert(&cache, "b", 100);
+        assert!(!cached(&cache, "b", 101));
+        assert!(!cached(&cache, "b", 100));
+    }
+
+    #[test]
+    fn files_bigger_than_the_cache_are_passed_through() {
+        let cache = FileCache::new(300);
+        insert(&cache, "a", 100);
+        let bytes = cache.insert(Path::new("b"), SystemTime::UNIX_EPOCH, vec![1; 301]);
+        assert_eq!(bytes.len(), 301);
+        assert!(!cached(&cache, "b", 301));
+        assert!(cached(&cache, "a", 100));
+    }
+
+    #[test]
+    fn removed_files_make_room() {
+        let cache = FileCache::new(300);
+        insert(&cache, "a", 200);
+        cache.remove(Path::new("a"));
+        cache.remove(Path::new("missing"));
+        assert!(!cached(&cache, "a", 200));
+        insert(&cache, "b", 300);
+        assert!(cached(&cache, "b", 300));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::request::Request;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Bodies smaller than this aren't worth the gzip framing overhead.
+pub const MIN_SIZE: u64 = 1024;
+
+/// Compression happens in memory so the compressed length can be sent up
+/// front; larger files are streamed as-is to keep memory use bounded.
+pub const MAX_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Pre-compressed sidecar files looked for next to a requested file, by
+/// extension and content coding, in order of preference.
+const SIDECARS: &[(&str, &str)] = &[("br", "br"), ("gz", "gzip")];
+
+/// Content codings the server can apply on the fly, in order of preference
+/// when the client likes several equally.
+pub const ENCODINGS: &[&str] = &["gzip", "deflate"];
+
+/// The codings listed in `Accept-Encoding`, lowercased, with their quality
+/// values. Entries with an unreadable quality are left out.
+fn accepted(value: &str) -> Vec<(String, f32)> {
+    value
+        .split(',')
+        .filter_map(|listed| {
+            let mut params = listed.split(';');
+            let name = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            let q = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(q) => q
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q))?,
+                None => 1.0,
+            };
+            Some((name, q))
+        })
+        .collect()
+}
+
+/// How much the client wants `coding`, falling back to the quality of `*`
+/// for codings it didn't list. `None` if neither is listed.
+fn quality(accepted: &[(String, f32)], coding: &str) -> Option<f32> {
+    let find = |name: &str| {
+        accepted
+            .iter()
+            .find(|(listed, _)| listed == name)
+            .map(|&(_, q)| q)
+    };
+    find(coding).or_else(|| find("*"))
+}
+
+/// Picks the coding from `codings` the client's `Accept-Encoding` rates
+/// highest, with ties going to the earlier one. `None` means sending the
+/// body as-is: there's no header, nothing offered is acceptable, or the
+/// client rates `identity` above all of them. Unless it's listed, `identity`
+/// ranks below any coding the client accepts.
+pub fn negotiate(request: &Request, codings: &[&'static str]) -> Option<&'static str> {
+    let accepted = accepted(request.header("Accept-Encoding")?);
+    let identity = quality(&accepted, "identity").unwrap_or(0.0);
+    let mut best: Option<(&'static str, f32)> = None;
+    for &coding in codings {
+        let q = quality(&accepted, coding).unwrap_or(0.0);
+        if q > 0.0 && q >= identity && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Whether a MIME type is text-like. Images, archives and other binary formats
+/// are usually compressed already, so gzipping them again only costs CPU.
+pub fn is_compressible(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || matches!(
+            essence,
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// The coding to compress a response of `size` bytes with the given type in,
+/// if it's worth compressing and the client accepts one.
+pub fn choose(request: &Request, mime_type: &str, size: u64) -> Option<&'static str> {
+    if (MIN_SIZE..=MAX_SIZE).contains(&size) && is_compressible(mime_type) {
+        negotiate(request, ENCODINGS)
+    } else {
+        None
+    }
+}
+
+/// Finds a pre-compressed copy of `path`, such as `app.js.br` next to
+/// `app.js`, in the coding the client prefers among those present. Copies
+/// older than the file itself are assumed to be stale and skipped, as are
+/// those `usable` turns down. Returns the copy's path and its content coding.
+pub fn precompressed(
+    path: &Path,
+    request: &Request,
+    usable: impl Fn(&Path) -> bool,
+) -> Option<(PathBuf, &'static str)> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    let sidecars: Vec<(PathBuf, &'static str)> = SIDECARS
+        .iter()
+        .filter_map(|&(extension, coding)| {
+            let mut sidecar = path.as_os_str().to_owned();
+            sidecar.push(".");
+            sidecar.push(extension);
+            let sidecar = PathBuf::from(sidecar);
+            // The copy never went through the checks on the request path,
+            // so it mustn't be a way around them
+            if fs::symlink_metadata(&sidecar).is_err() || !usable(&sidecar) {
+                return None;
+            }
+            let metadata = fs::metadata(&sidecar).ok()?;
+            let fresh = metadata.modified().is_ok_and(|time| time >= modified);
+            (metadata.is_file() && fresh).then_some((sidecar, coding))
+        })
+        .collect();
+    let codings: Vec<&'static str> = sidecars.iter().map(|&(_, coding)| coding).collect();
+    let coding = negotiate(request, &codings)?;
+    sidecars.into_iter().find(|&(_, listed)| listed == coding)
+}
+
+/// Compresses everything `reader` yields into memory, in `coding`, one of
+/// `ENCODINGS`.
+pub fn compress<R: Read>(reader: &mut R, coding: &str) -> io::Result<Vec<u8>> {
+    if coding == "deflate" {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()
+    } else {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn request(accept_encoding: Option<&str>) -> Request {
+        let header = accept_encoding
+            .map(|value| format!("Accept-Encoding: {}\r\n", value))
+            .unwrap_or_default();
+        Request::parse(&format!("GET / HTTP/1.1\r\n{}\r\n", header)).unwrap()
+    }
+
+    fn negotiated(accept_encoding: Option<&str>) -> Option<&'static str> {
+        negotiate(&request(accept_encoding), ENCODINGS)
+    }
+
+    #[test]
+    fn codings_are_picked_by_quality() {
+        assert_eq!(negotiated(None), None);
+        assert_eq!(negotiated(Some("")), None);
+        assert_eq!(negotiated(Some("gzip")), Some("gzip"));
+        assert_eq!(negotiated(Some("deflate, gzip")), Some("gzip"));
+        assert_eq!(negotiated(Some("gzip;q=0.5, deflate")), Some("deflate"));
+        assert_eq!(negotiated(Some("GZIP ; q=0.8")), Some("gzip"));
+        assert_eq!(negotiated(Some("br")), None);
+    }
+
+    #[test]
+    fn zero_and_unreadable_qualities_turn_codings_down() {
+        assert_eq!(negotiated(Some("gzip;q=0")), None);
+        assert_eq!(negotiated(Some("gzip;q=0, deflate;q=0.1")), Some("deflate"));
+        assert_eq!(negotiated(Some("gzip;q=2")), None);
+        assert_eq!(negotiated(Some("gzip;q=high")), None);
+    }
+
+    #[test]
+    fn wildcards_cover_unlisted_codings() {
+        assert_eq!(negotiated(Some("*")), Some("gzip"));
+        assert_eq!(negotiated(Some("gzip;q=0, *")), Some("deflate"));
+        assert_eq!(negotiated(Some("*;q=0")), None);
+        assert_eq!(negotiated(Some("*;q=0, deflate")), Some("deflate"));
+    }
+
+    #[test]
+    fn identity_can_outrank_every_coding() {
+        assert_eq!(negotiated(Some("identity, gzip;q=0.5")), None);
+        assert_eq!(negotiated(Some("identity;q=0.5, gzip")), Some("gzip"));
+        assert_eq!(negotiated(Some("identity, gzip")), Some("gzip"));
+    }
+
+    #[test]
+    fn only_text_of_a_moderate_size_is_compressed() {
+        let request = request(Some("gzip"));
+        assert_eq!(
+            choose(&request, "text/html; charset=utf-8", 4096),
+            Some("gzip")
+        );
+        assert_eq!(choose(&request, "application/json", MIN_SIZE), Some("gzip"));
+        assert_eq!(choose(&request, "image/svg+xml", MAX_SIZE), Some("gzip"));
+        assert_eq!(choose(&request, "text/plain", MIN_SIZE - 1), None);
+        assert_eq!(choose(&request, "text/plain", MAX_SIZE + 1), None);
+        assert_eq!(choose(&request, "image/png", 4096), None);
+        assert_eq!(choose(&request, "application/zip", 4096), None);
+    }
+
+    #[test]
+    fn sidecars_must_be_fresh_files() {
+        let dir = TempDir::new("sidecars");
+        dir.write("app.js", "console.log(1)");
+        dir.write("app.js.br", "new");
+        dir.write("app.js.gz", "old");
+        let path = dir.path().join("app.js");
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let stale = modified - std::time::Duration::from_secs(60);
+        let gz = fs::File::options()
+            .write(true)
+            .open(dir.path().join("app.js.gz"));
+        gz.unwrap().set_modified(stale).unwrap();
+
+        let found = precompressed(&path, &request(Some("gzip, br")), |_| true);
+        assert_eq!(found, Some((dir.path().join("app.js.br"), "br")));
+        assert_eq!(precompressed(&path, &request(Some("gzip")), |_| true), None);
+        assert_eq!(precompressed(&path, &request(Some("br")), |_| false), None);
+        assert_eq!(precompressed(&path, &request(None), |_| true), None);
+    }
+}
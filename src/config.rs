@@ -0,0 +1,656 @@
+use crate::acl::IpRange;
+use crate::auth::BasicAuth;
+use crate::blocklist::Blocklist;
+use crate::bundle::{self, Bundle};
+use crate::cache::FileCache;
+use crate::logger::{LogFormat, LogLevel};
+use crate::mime;
+use crate::request;
+use ignore::gitignore::Gitignore;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: simple-file-server [ROOT_DIR] [OPTIONS]
+
+Options:
+  --host HOST      Address to listen on (default: 127.0.0.1)
+  --port PORT      Port to listen on (default: 8080)
+  --threads N      Number of worker threads (default: number of CPUs)
+  --max-connections N
+                   Answer 503 once N connections are open (default: no limit)
+  --open           Open the server in the default browser once it's started
+  --no-index       List directories even when they contain an index file
+  --index-files F  Comma-separated index file names (default: index.html,index.htm)
+  --health-path P  Answer health checks at P (default: /_healthz)
+  --base-path P    Serve everything under the URL path P, e.g. behind a proxy
+  --spa            Serve the root index file for paths that don't exist
+  --log-format F   Access log format, `text` or `json` (default: text)
+  --log-file FILE  Append the access log to FILE instead of printing it
+  --quiet          Only log errors, not every request
+  --verbose        Also log request headers and how paths are resolved
+  --404-page FILE  Serve FILE as the body of 404 responses
+  --error-pages DIR
+                   Serve 403.html, 404.html and 50x.html from DIR for errors
+  --template FILE  Render directory listings with the HTML template in FILE
+  --page-size N    Entries per page of a directory listing (default: 1000)
+  --mime-types FILE
+                   Extra content types by extension, in Apache mime.types format
+  --no-favicon     Don't answer /favicon.ico with the built-in icon
+  --mount P=DIR    Serve DIR, or a ZIP or tar archive, under the URL prefix P (repeatable)
+  --auth U:P       Require HTTP Basic authentication as user U, password P
+  --allow CIDR     Only accept clients from this IP range, e.g. 10.0.0.0/8 (repeatable)
+  --trust-proxy    Take client addresses from X-Forwarded-For
+  --timeout SECS   Time allowed for a client to send its request headers (default: 30)
+  --writable       Accept file uploads into served directories
+  --overwrite      Let uploads replace existing files (with --writable)
+  --upload-dir DIR Only accept uploads and deletions inside DIR, relative to the root
+  --max-upload N   Largest upload accepted, e.g. 500K, 20M or 1G (default: 100M)
+  --max-body N     Largest request body accepted at all (default: 1G)
+  --max-age SECS   How long clients may cache files, at most a year (default: 3600)
+  --rate-limit N   Cap each connection at N bytes per second each way, e.g. 500K
+  --cache-size MB  Keep up to MB MiB of small files in memory (default: off)
+  --watch          Log changes to served files and drop them from the cache
+  --watch-interval SECS
+                   How often --watch rescans the whole tree (default: 1)
+  --follow-symlinks
+                   Serve symlinks that lead somewhere inside the root
+  --no-follow-symlinks
+                   Refuse symlinks again, the default, undoing --follow-symlinks
+  --show-hidden    List and serve files whose names start with a dot
+  --max-depth N    Refuse directories more than N levels below the root
+  --unblock PATTERN
+                   Serve files matching PATTERN from the list of sensitive files
+  --metrics        Serve request counters at /_metrics for Prometheus
+  --recent         List the most recently modified files at /_recent
+  --cors ORIGIN    Allow cross-origin requests from ORIGIN, or `*` for any
+  --cert FILE      Serve HTTPS with the PEM certificate chain in FILE (needs --key)
+  --key FILE       Private key for --cert, in PEM format";
+
+/// File at the root of a mount listing paths to hide, one glob per line in
+/// `.gitignore` syntax.
+const IGNORE_FILE: &str = ".serverignore";
+
+/// The longest `--max-age` accepted, one year, which keeps the `Expires`
+/// dates it leads to well within what an HTTP date can show.
+const MAX_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
+/// A directory, or an archive standing in for one, served under a URL
+/// prefix.
+pub struct Mount {
+    /// The URL path the directory appears under, without a trailing slash, so
+    /// the root mount's prefix is empty.
+    pub prefix: String,
+    /// The prefix as it appears in links, with `--base-path` in front and
+    /// URL-encoded.
+    pub url_prefix: String,
+    /// The directory, as an absolute path with symlinks resolved, so paths
+    /// under it can be compared reliably.
+    pub root: PathBuf,
+    /// Patterns from the root's ignore file, if it has one.
+    ignore: Option<Gitignore>,
+    /// The archive's entries, when the root is a ZIP or tar file.
+    pub bundle: Option<Bundle>,
+}
+
+impl Mount {
+    /// Mounts `root` under `prefix`, loading its ignore file if present.
+    /// A ZIP or tar file is mounted as the directory tree inside it.
+    fn new(prefix: String, root: PathBuf) -> Result<Mount, String> {
+        let root = root
+            .canonicalize()
+            .map_err(|e| format!("Cannot serve {}: {}", root.display(), e))?;
+        let bundle = if root.is_dir() {
+            None
+        } else if root.is_file() && bundle::is_bundle(&root) {
+            Some(Bundle::open(&root)?)
+        } else {
+            return Err(format!(
+                "Cannot serve {}: not a directory or a ZIP or tar archive",
+                root.display()
+            ));
+        };
+
+        let ignore_file = Some(root.join(IGNORE_FILE)).filter(|file| file.is_file());
+        let ignore = match ignore_file {
+            Some(file) => match Gitignore::new(&file) {
+                (_, Some(e)) => return Err(format!("Invalid {}: {}", file.display(), e)),
+                (ignore, None) => Some(ignore),
+            },
+            None => None,
+        };
+        Ok(Mount {
+            url_prefix: request::encode_path(&prefix),
+            prefix,
+            root,
+            ignore,
+            bundle,
+        })
+    }
+
+    /// Whether `path`, a path inside this mount, is hidden by the
+    /// ignore file. The ignore file itself is always hidden.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // Without one, only its own name is hidden, so it can't be created
+        let Some(ignore) = &self.ignore else {
+            return path == self.root.join(IGNORE_FILE);
+        };
+        let Ok(relative_path) = path.strip_prefix(ignore.path()) else {
+            return false;
+        };
+        if relative_path.as_os_str().is_empty() {
+            return false;
+        }
+        relative_path == Path::new(IGNORE_FILE)
+            || ignore
+                .matched_path_or_any_parents(relative_path, is_dir)
+                .is_ignore()
+    }
+
+    /// Whether `path`, which was found without going through
+    /// `parse_request`, may be served as far as symlinks go: it isn't one,
+    /// or following them is allowed and it leads inside this mount.
+    pub fn allows_link(&self, path: &Path, follow_symlinks: bool) -> bool {
+        if !path.is_symlink() {
+            return true;
+        }
+        follow_symlinks
+            && path
+                .canonicalize()
+                .is_ok_and(|real_path| real_path.starts_with(&self.root))
+    }
+
+    /// If `path` falls under this mount, returns the part after the prefix.
+    pub fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        request::strip_path_prefix(path, &self.prefix)
+    }
+}
+
+pub struct Config {
+    pub mounts: Vec<Mount>,
+    pub host: String,
+    pub port: u16,
+    pub threads: usize,
+    /// Open connections beyond which new ones get `503`.
+    pub max_connections: Option<usize>,
+    /// Open the root URL in a browser once listening.
+    pub open: bool,
+    /// Serve a directory's index file instead of its listing.
+    pub serve_index: bool,
+    /// Names tried in order when serving a directory's index.
+    pub index_files: Vec<String>,
+    /// Path answered with a plain `ok` for load balancer health checks.
+    pub health_path: String,
+    /// URL path everything is served under, without a trailing slash, for
+    /// hosting behind a reverse proxy at a subpath. Empty by default.
+    pub base_path: String,
+    /// Fall back to the root index file for missing paths without an
+    /// extension, for single-page apps.
+    pub spa: bool,
+    pub log_format: LogFormat,
+    pub log_level: LogLevel,
+    /// File to append the access log to, rather than stdout.
+    pub log_file: Option<PathBuf>,
+    pub not_found_page: Option<PathBuf>,
+    /// The contents of the `--template` file directory listings are
+    /// rendered with, in place of the built-in page.
+    pub template: Option<String>,
+    /// How many entries a page of a directory listing shows, unless
+    /// `?per=` asks for another number.
+    pub page_size: usize,
+    /// Directory holding `403.html`, `404.html` and `50x.html` to send in
+    /// place of the built-in error pages.
+    pub error_pages: Option<PathBuf>,
+    /// Content types from `--mime-types`, by lowercase extension.
+    pub mime_types: HashMap<String, String>,
+    /// Answer `/favicon.ico` with a built-in icon when the root has none.
+    pub favicon: bool,
+    pub auth: Option<BasicAuth>,
+    /// IP ranges clients must connect from. Empty allows everyone.
+    pub allow: Vec<IpRange>,
+    /// Believe `X-Forwarded-For` about who the client is, for logs and
+    /// `--allow`.
+    pub trust_proxy: bool,
+    /// How long a client has to send its request headers.
+    pub timeout: Duration,
+    /// Accept multipart uploads into served directories.
+    pub writable: bool,
+    /// Let uploads replace files that already exist.
+    pub overwrite: bool,
+    /// Subdirectory of each root that uploads and deletions are confined
+    /// to, when set.
+    pub upload_dir: Option<PathBuf>,
+    /// Largest upload body accepted, in bytes.
+    pub max_upload: u64,
+    /// Bytes per second each connection may send, and receive.
+    pub rate_limit: Option<u64>,
+    /// How long clients may reuse a file without checking back. HTML is
+    /// always revalidated, and images, CSS and JS get longer.
+    pub max_age: Duration,
+    /// Largest request body of any kind, in bytes. Bigger ones are refused
+    /// without being read.
+    pub max_body: u64,
+    /// In-memory cache of small files, when enabled.
+    pub cache: Option<FileCache>,
+    /// Scan the mounts for changes, logging them and keeping the cache fresh.
+    pub watch: bool,
+    /// How long `--watch` waits between scans of the mounts.
+    pub watch_interval: Duration,
+    /// Serve symlinks whose targets are inside the root, rather than
+    /// refusing every symlink.
+    pub follow_symlinks: bool,
+    /// List and serve dotfiles, which are hidden by default.
+    pub show_hidden: bool,
+    /// How many directory levels below a mount's root may be browsed, and
+    /// the files in them served.
+    pub max_depth: Option<usize>,
+    /// Sensitive files that are never served, such as SSH keys.
+    pub blocklist: Blocklist,
+    /// Serve request counters at `/_metrics`.
+    pub metrics: bool,
+    /// List the most recently modified files at `/_recent`.
+    pub recent: bool,
+    /// Origin allowed to make cross-origin requests, or `*`.
+    pub cors: Option<String>,
+    /// Certificate chain and private key files, when serving HTTPS.
+    pub tls: Option<(PathBuf, PathBuf)>,
+}
+
+impl Config {
+    /// Whether `path` may be changed under `--upload-dir`, which confines
+    /// uploads and deletions to one directory of each mount. Deleting that
+    /// directory itself isn't allowed, so `strictly_inside` is set for it.
+    pub fn may_change(&self, path: &Path, mount: &Mount, strictly_inside: bool) -> bool {
+        let Some(upload_dir) = &self.upload_dir else {
+            return true;
+        };
+        let Ok(real_upload_dir) = mount.root.join(upload_dir).canonicalize() else {
+            return false;
+        };
+        let real_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        real_path.starts_with(&real_upload_dir)
+            && !(strictly_inside && real_path == real_upload_dir)
+    }
+
+    /// Whether `path`, inside `mount`, is a sensitive file that's never
+    /// served: a match for the blocklist, or the server's own TLS
+    /// certificate and key.
+    pub fn is_blocked(&self, path: &Path, mount: &Mount, is_dir: bool) -> bool {
+        let real_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Ok(relative_path) = real_path.strip_prefix(&mount.root) {
+            if self.blocklist.matches(relative_path, is_dir) {
+                return true;
+            }
+        }
+        self.tls.as_ref().is_some_and(|(cert, key)| {
+            [cert, key]
+                .iter()
+                .any(|file| file.canonicalize().is_ok_and(|file| file == real_path))
+        })
+    }
+
+    /// Whether `--max-depth` lets clients reach `relative_path`, a path
+    /// inside a mount: directories at most that many levels down, and the
+    /// files directly in them.
+    pub fn within_depth(&self, relative_path: &Path, is_dir: bool) -> bool {
+        let Some(max_depth) = self.max_depth else {
+            return true;
+        };
+        let depth = relative_path.components().count();
+        let dir_depth = if is_dir {
+            depth
+        } else {
+            depth.saturating_sub(1)
+        };
+        dir_depth <= max_depth
+    }
+
+    /// Whether a client at `ip` may use the server.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip))
+    }
+
+    /// Builds the server configuration from the command line arguments
+    /// (excluding the program name). Any value that isn't a flag is treated
+    /// as the root directory to serve.
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Config, String> {
+        let mut config = Config {
+            mounts: Vec::new(),
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            max_connections: None,
+            open: false,
+            serve_index: true,
+            index_files: vec!["index.html".to_string(), "index.htm".to_string()],
+            health_path: "/_healthz".to_string(),
+            base_path: String::new(),
+            spa: false,
+            log_format: LogFormat::Text,
+            log_level: LogLevel::Normal,
+            log_file: None,
+            not_found_page: None,
+            template: None,
+            page_size: 1000,
+            error_pages: None,
+            mime_types: HashMap::new(),
+            favicon: true,
+            auth: None,
+            allow: Vec::new(),
+            trust_proxy: false,
+            timeout: Duration::from_secs(30),
+            writable: false,
+            overwrite: false,
+            upload_dir: None,
+            max_upload: 100 * 1024 * 1024,
+            rate_limit: None,
+            max_body: 1024 * 1024 * 1024,
+            max_age: Duration::from_secs(3600),
+            cache: None,
+            watch: false,
+            watch_interval: Duration::from_secs(1),
+            follow_symlinks: false,
+            show_hidden: false,
+            max_depth: None,
+            blocklist: Blocklist::new(&[])?,
+            metrics: false,
+            recent: false,
+            cors: None,
+            tls: None,
+        };
+        let mut root_dir = None;
+        let mut unblocked = Vec::new();
+        let mut cert = None;
+        let mut key = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--host" => config.host = flag_value(&mut args, "--host")?,
+                "--port" => {
+                    let value = flag_value(&mut args, "--port")?;
+                    config.port = value
+                        .parse()
+                        .map_err(|_| format!("Invalid port: {}", value))?;
+                }
+                "--max-connections" => {
+                    let value = flag_value(&mut args, "--max-connections")?;
+                    config.max_connections = match value.parse() {
+                        Ok(max) if max > 0 => Some(max),
+                        _ => return Err(format!("Invalid connection limit: {}", value)),
+                    };
+                }
+                "--open" => config.open = true,
+                "--threads" => {
+                    let value = flag_value(&mut args, "--threads")?;
+                    config.threads = match value.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("Invalid thread count: {}", value)),
+                    };
+                }
+                "--no-index" => config.serve_index = false,
+                "--health-path" => {
+                    let path = flag_value(&mut args, "--health-path")?;
+                    if !path.starts_with('/') {
+                        return Err(format!("Invalid health check path: {}", path));
+                    }
+                    config.health_path = path;
+                }
+                "--index-files" => {
+                    let value = flag_value(&mut args, "--index-files")?;
+                    config.index_files = value
+                        .split(',')
+                        .map(|name| name.trim().to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect();
+                    // Names are joined onto directories, so they can't climb out
+                    if config.index_files.is_empty()
+                        || config
+                            .index_files
+                            .iter()
+                            .any(|name| name.contains(['/', '\\']) || name == "..")
+                    {
+                        return Err(format!("Invalid index file names: {}", value));
+                    }
+                }
+                "--spa" => config.spa = true,
+                "--log-format" => {
+                    config.log_format = match flag_value(&mut args, "--log-format")?.as_str() {
+                        "text" => LogFormat::Text,
+                        "json" => LogFormat::Json,
+                        other => return Err(format!("Unknown log format: {}", other)),
+                    };
+                }
+                "--log-file" => {
+                    config.log_file = Some(PathBuf::from(flag_value(&mut args, "--log-file")?))
+                }
+                "--quiet" => config.log_level = LogLevel::Quiet,
+                "--verbose" => config.log_level = LogLevel::Verbose,
+                "--404-page" => {
+                    config.not_found_page =
+                        Some(PathBuf::from(flag_value(&mut args, "--404-page")?))
+                }
+                "--error-pages" => {
+                    let dir = PathBuf::from(flag_value(&mut args, "--error-pages")?);
+                    if !dir.is_dir() {
+                        return Err(format!("Invalid error page directory: {}", dir.display()));
+                    }
+                    config.error_pages = Some(dir);
+                }
+                "--template" => {
+                    let path = flag_value(&mut args, "--template")?;
+                    let template = fs::read_to_string(&path)
+                        .map_err(|e| format!("Cannot read template {}: {}", path, e))?;
+                    config.template = Some(template);
+                }
+                "--page-size" => {
+                    let value = flag_value(&mut args, "--page-size")?;
+                    config.page_size = match value.parse() {
+                        Ok(size) if size > 0 => size,
+                        _ => return Err(format!("Invalid page size: {}", value)),
+                    };
+                }
+                "--mime-types" => {
+                    let path = flag_value(&mut args, "--mime-types")?;
+                    let types = fs::read_to_string(&path)
+                        .map_err(|e| format!("Cannot read MIME types {}: {}", path, e))?;
+                    config.mime_types.extend(mime::parse_types(&types));
+                }
+                "--no-favicon" => config.favicon = false,
+                "--base-path" => {
+                    let value = flag_value(&mut args, "--base-path")?;
+                    let path = value.trim_matches('/');
+                    config.base_path = if path.is_empty() {
+                        String::new()
+                    } else {
+                        format!("/{}", path)
+                    };
+                }
+                "--mount" => {
+                    let value = flag_value(&mut args, "--mount")?;
+                    let (prefix, dir) = value
+                        .split_once('=')
+                        .ok_or_else(|| format!("Invalid mount, expected PREFIX=DIR: {}", value))?;
+                    let prefix = format!("/{}", prefix.trim_matches('/'));
+                    let prefix = prefix.trim_end_matches('/').to_string();
+                    if config.mounts.iter().any(|mount| mount.prefix == prefix) {
+                        return Err(format!("Duplicate mount prefix: {}", value));
+                    }
+                    config.mounts.push(Mount::new(prefix, PathBuf::from(dir))?);
+                }
+                "--auth" => {
+                    config.auth = Some(BasicAuth::parse(&flag_value(&mut args, "--auth")?)?)
+                }
+                "--allow" => config
+                    .allow
+                    .push(IpRange::parse(&flag_value(&mut args, "--allow")?)?),
+                "--trust-proxy" => config.trust_proxy = true,
+                "--timeout" => {
+                    let value = flag_value(&mut args, "--timeout")?;
+                    config.timeout = match value.parse() {
+                        Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                        _ => return Err(format!("Invalid timeout: {}", value)),
+                    };
+                }
+                "--writable" => config.writable = true,
+                "--overwrite" => config.overwrite = true,
+                "--upload-dir" => {
+                    let value = flag_value(&mut args, "--upload-dir")?;
+                    let dir = PathBuf::from(value.trim_start_matches('/'));
+                    if dir
+                        .components()
+                        .any(|component| component == Component::ParentDir)
+                    {
+                        return Err(format!("Invalid upload directory: {}", value));
+                    }
+                    config.upload_dir = Some(dir);
+                }
+                "--max-upload" => {
+                    let value = flag_value(&mut args, "--max-upload")?;
+                    config.max_upload = parse_size(&value)
+                        .ok_or_else(|| format!("Invalid upload size: {}", value))?;
+                }
+                "--rate-limit" => {
+                    let value = flag_value(&mut args, "--rate-limit")?;
+                    let rate = parse_size(&value)
+                        .filter(|&rate| rate > 0)
+                        .ok_or_else(|| format!("Invalid rate limit: {}", value))?;
+                    config.rate_limit = Some(rate);
+                }
+                "--max-body" => {
+                    let value = flag_value(&mut args, "--max-body")?;
+                    config.max_body = parse_size(&value)
+                        .ok_or_else(|| format!("Invalid body size: {}", value))?;
+                }
+                "--max-age" => {
+                    let value = flag_value(&mut args, "--max-age")?;
+                    config.max_age = match value.parse() {
+                        Ok(secs) if secs <= MAX_MAX_AGE => Duration::from_secs(secs),
+                        _ => return Err(format!("Invalid max age: {}", value)),
+                    };
+                }
+                "--cache-size" => {
+                    let value = flag_value(&mut args, "--cache-size")?;
+                    let bytes = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&mib| mib > 0)
+                        .and_then(|mib| mib.checked_mul(1024 * 1024))
+                        .ok_or_else(|| format!("Invalid cache size: {}", value))?;
+                    config.cache = Some(FileCache::new(bytes));
+                }
+                "--watch" => config.watch = true,
+                "--watch-interval" => {
+                    let value = flag_value(&mut args, "--watch-interval")?;
+                    config.watch_interval = match value.parse() {
+                        Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                        _ => return Err(format!("Invalid watch interval: {}", value)),
+                    };
+                }
+                "--follow-symlinks" => config.follow_symlinks = true,
+                "--no-follow-symlinks" => config.follow_symlinks = false,
+                "--show-hidden" => config.show_hidden = true,
+                "--max-depth" => {
+                    let value = flag_value(&mut args, "--max-depth")?;
+                    config.max_depth = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid max depth: {}", value))?,
+                    );
+                }
+                "--unblock" => unblocked.push(flag_value(&mut args, "--unblock")?),
+                "--metrics" => config.metrics = true,
+                "--recent" => config.recent = true,
+                "--cors" => config.cors = Some(flag_value(&mut args, "--cors")?),
+                "--cert" => cert = Some(PathBuf::from(flag_value(&mut args, "--cert")?)),
+                "--key" => key = Some(PathBuf::from(flag_value(&mut args, "--key")?)),
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+                _ if root_dir.is_none() => root_dir = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument: {}", arg)),
+            }
+        }
+
+        config.blocklist = Blocklist::new(&unblocked)?;
+
+        config.tls = match (cert, key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => return Err("--cert and --key must be given together".to_string()),
+        };
+
+        // The root directory is mounted at `/`. Without any explicit mounts it
+        // defaults to the current directory.
+        let root_dir = match root_dir {
+            Some(dir) => Some(dir),
+            None if config.mounts.is_empty() => Some(
+                env::current_dir()
+                    .map_err(|e| format!("Failed to get current directory: {}", e))?,
+            ),
+            None => None,
+        };
+        if let Some(root) = root_dir {
+            if config.mounts.iter().any(|mount| mount.prefix.is_empty()) {
+                return Err("The root directory conflicts with a mount at /".to_string());
+            }
+            config.mounts.push(Mount::new(String::new(), root)?);
+        }
+        for mount in &mut config.mounts {
+            mount.url_prefix =
+                request::encode_path(&format!("{}{}", config.base_path, mount.prefix));
+        }
+
+        Ok(config)
+    }
+
+    /// Strips `--base-path` from a request path, giving the path the server
+    /// routes on. Paths outside the base path give `None`.
+    pub fn strip_base_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        request::strip_path_prefix(path, &self.base_path)
+    }
+
+    /// `--base-path` as it appears in links, URL-encoded.
+    pub fn base_url(&self) -> String {
+        request::encode_path(&self.base_path)
+    }
+
+    /// Finds the mount serving `path`, preferring the longest matching prefix,
+    /// along with the rest of the path inside that mount.
+    pub fn find_mount<'a>(&self, path: &'a str) -> Option<(&Mount, &'a str)> {
+        self.mounts
+            .iter()
+            .filter_map(|mount| mount.strip(path).map(|rest| (mount, rest)))
+            .max_by_key(|(mount, _)| mount.prefix.len())
+    }
+
+    /// The address string to bind to. IPv6 addresses are bracketed to keep
+    /// them apart from the port, and may be given with or without brackets.
+    pub fn bind_addr(&self) -> String {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        if host.parse::<Ipv6Addr>().is_ok() {
+            format!("[{}]:{}", host, self.port)
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+fn flag_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+/// Parses a byte count with an optional binary `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last()? {
+        (i, 'K' | 'k') => (&value[..i], 1024),
+        (i, 'M' | 'm') => (&value[..i], 1024 * 1024),
+        (i, 'G' | 'g') => (&value[..i], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
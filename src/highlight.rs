@@ -0,0 +1,94 @@
+use crate::html;
+use crate::mime;
+use crate::request::Request;
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Largest source file highlighted; bigger ones are sent as plain text.
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// Whether to send the file at `path` as a highlighted page instead of plain
+/// text: only for browsers, and never when `?raw=1` asks for the original.
+pub fn should_render(path: &Path, request: &Request, size: u64) -> bool {
+    size <= MAX_SIZE && html::wants_rendered(request) && is_source(path)
+}
+
+/// Whether `path` is a file that browsers get as a highlighted page.
+pub fn is_source(path: &Path) -> bool {
+    syntax_for(path).is_some()
+}
+
+/// Renders a source file as a page with highlighting and line numbers.
+pub fn render(path: &Path, title: &str, source: &str) -> String {
+    let syntaxes = syntax_set();
+    let syntax = syntax_for(path).unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, theme());
+
+    let mut body = String::from("<p class=\"raw\"><a href=\"?raw=1\">View raw</a></p>");
+    body.push_str("<pre class=\"code\">");
+    for (index, line) in LinesWithEndings::from(source).enumerate() {
+        let number = index + 1;
+        body.push_str(&format!(
+            "<a class=\"line-number\" id=\"L{0}\" href=\"#L{0}\">{0}</a>",
+            number
+        ));
+        let highlighted = highlighter
+            .highlight_line(line, syntaxes)
+            .ok()
+            .and_then(|ranges| {
+                styled_line_to_highlighted_html(&ranges, IncludeBackground::No).ok()
+            });
+        match highlighted {
+            Some(highlighted) => body.push_str(&highlighted),
+            None => body.push_str(&html::escape(line)),
+        }
+    }
+    body.push_str("</pre>");
+
+    html::page(title, &body)
+}
+
+/// The syntax to highlight `path` with, picked by file name or extension.
+/// Pages, images and Markdown are left for the browser or the Markdown
+/// renderer, and other plain text formats get line numbers only.
+fn syntax_for(path: &Path) -> Option<&'static SyntaxReference> {
+    let syntaxes = syntax_set();
+    let name = path.file_name()?.to_str()?;
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+
+    let content_type = mime::from_extension(extension);
+    if content_type.is_some_and(|content_type| {
+        content_type.starts_with("text/html")
+            || content_type.starts_with("image/")
+            || content_type == "text/markdown"
+    }) {
+        return None;
+    }
+
+    syntaxes
+        .find_syntax_by_extension(name)
+        .or_else(|| syntaxes.find_syntax_by_extension(extension))
+        .filter(|syntax| syntax.name != "Plain Text")
+        .or_else(|| {
+            let plain = content_type == Some("text/plain") && extension != "txt";
+            plain.then(|| syntaxes.find_syntax_plain_text())
+        })
+}
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        themes.themes.remove("InspiredGitHub").unwrap_or_default()
+    })
+}
@@ -0,0 +1,182 @@
+use crate::compress;
+use crate::request::Request;
+use crate::response::Response;
+use std::io::Write;
+
+/// Shared look for every page the server generates. Colours come from
+/// variables so the dark theme only has to swap those.
+pub const STYLE: &str = r#"
+            :root { --text: #1f2328; --background: #ffffff; --muted: #57606a;
+                --border: #d0d7de; --link: #0366d6; --code: #f6f8fa; color-scheme: light; }
+            :root[data-theme="dark"] { --text: #e6edf3; --background: #0d1117; --muted: #8d96a0;
+                --border: #30363d; --link: #4493f8; --code: #161b22; color-scheme: dark; }
+            @media (prefers-color-scheme: dark) {
+                :root:not([data-theme="light"]) { --text: #e6edf3; --background: #0d1117;
+                    --muted: #8d96a0; --border: #30363d; --link: #4493f8; --code: #161b22;
+                    color-scheme: dark; }
+            }
+            body { font-family: Arial, sans-serif; color: var(--text); background: var(--background); }
+            table { border-collapse: collapse; }
+            th, td { padding: 4px 12px; text-align: left; }
+            th { border-bottom: 1px solid var(--border); }
+            td.size, th.size { text-align: right; }
+            td.size, td.modified { color: var(--muted); white-space: nowrap; }
+            a { text-decoration: none; color: var(--link); }
+            a:hover { text-decoration: underline; }
+            pre { background: var(--code); padding: 12px; overflow-x: auto; }
+            code { background: var(--code); }
+            article { max-width: 50em; }
+            p.raw { font-size: small; }
+            p.totals { color: var(--muted); font-size: small; }
+            nav.breadcrumbs { margin-bottom: 12px; }
+            nav.pages { margin: 12px 0; }
+            form.upload { margin-top: 16px; }
+            section.readme { margin-top: 24px; padding-top: 8px; border-top: 1px solid var(--border); }
+            form.filter { margin-bottom: 12px; }
+            section.preview { float: right; width: 40%; margin-left: 16px; padding: 8px;
+                border: 1px solid var(--border); overflow: hidden; }
+            section.preview h2 { margin-top: 0; font-size: medium; }
+            section.preview img { max-width: 100%; }
+            a.preview { font-size: small; opacity: 0.6; }
+            img.thumb { max-width: 32px; max-height: 32px; vertical-align: middle; }
+            /* The highlighting colours assume a light background */
+            pre.code { background: #f6f8fa; color: #1f2328; }
+            pre.code .line-number { display: inline-block; min-width: 3em; margin-right: 1em;
+                color: #8c959f; text-align: right; user-select: none; }
+            button.theme { position: fixed; top: 8px; right: 8px; cursor: pointer;
+                background: none; border: 1px solid var(--border); border-radius: 4px; }
+"#;
+
+/// Applies the theme saved in `localStorage` before the page is drawn, so
+/// it doesn't flash in the other one. Without a saved choice the browser's
+/// `prefers-color-scheme` decides.
+const THEME_SCRIPT: &str = r#"
+            try {
+                const saved = localStorage.getItem("theme");
+                if (saved) document.documentElement.dataset.theme = saved;
+            } catch (e) {}
+            function toggleTheme() {
+                const root = document.documentElement;
+                const dark = root.dataset.theme
+                    ? root.dataset.theme === "dark"
+                    : matchMedia("(prefers-color-scheme: dark)").matches;
+                root.dataset.theme = dark ? "light" : "dark";
+                try { localStorage.setItem("theme", root.dataset.theme); } catch (e) {}
+            }
+"#;
+
+/// Wraps `body` (already valid HTML) in a complete, styled page.
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"
+    <!DOCTYPE html>
+    <html>
+    <head>
+        <meta charset="utf-8">
+        <title>{}</title>
+        <style>{}        </style>
+        <script>{}        </script>
+    </head>
+    <body>
+        <button class="theme" onclick="toggleTheme()" title="Switch between light and dark">🌓</button>{}
+    </body>
+    </html>"#,
+        escape(title),
+        STYLE,
+        THEME_SCRIPT,
+        body
+    )
+}
+
+/// Fills in a `--template`, replacing each `{{name}}` with its value from
+/// `values`. Values are inserted as they are and never scanned for
+/// placeholders themselves. Unknown placeholders are left alone.
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            let name = after[..end].trim();
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                filled.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                filled.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Whether a browser is asking for the file, so it can be shown as a page
+/// rather than as-is. `?raw=1` and `?download=1` always get the original
+/// bytes.
+pub fn wants_rendered(request: &Request) -> bool {
+    request
+        .header("Accept")
+        .is_some_and(|accept| accept.contains("text/html"))
+        && request.query_param("raw").as_deref() != Some("1")
+        && request.query_param("download").as_deref() != Some("1")
+}
+
+/// Escapes text for use in HTML content and attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Sends a generated page, compressed when the client accepts it. `headers` are
+/// extra header lines to include, each ending in `\r\n`.
+pub fn send(
+    response: &mut Response,
+    request: &Request,
+    content_type: &str,
+    headers: &str,
+    body: String,
+) {
+    let mut response_body = body.into_bytes();
+    let mut content_encoding = String::new();
+    if let Some(coding) = compress::choose(request, content_type, response_body.len() as u64) {
+        if let Ok(compressed) = compress::compress(&mut response_body.as_slice(), coding) {
+            response_body = compressed;
+            content_encoding = format!("Content-Encoding: {}\r\n", coding);
+        }
+    }
+
+    let headers = format!(
+        "Content-Type: {}\r\n{}{}Vary: Accept, Accept-Encoding\r\nContent-Length: {}\r\n",
+        content_type,
+        headers,
+        content_encoding,
+        response_body.len()
+    );
+
+    if response.write_head(200, &headers).is_err() {
+        return;
+    }
+    if !request.is_head() && response.write_all(&response_body).is_err() {
+        return;
+    }
+    response.flush().unwrap_or(());
+}
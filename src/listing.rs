@@ -0,0 +1,1265 @@
+use crate::archive;
+use crate::bundle::{self, Bundle};
+use crate::config::{Config, Mount};
+use crate::html;
+use crate::markdown;
+use crate::request::{self, Request};
+use crate::response::{self, Response};
+use crate::thumbnail;
+use serde_json::json;
+use std::cmp::{Ordering, Reverse};
+use std::fs;
+use std::io::Read;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::path::Path;
+use std::str::Chars;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::{DirEntry, WalkDir};
+
+/// Files shown under a directory's listing, in order of preference.
+const README_NAMES: &[&str] = &[
+    "README.md",
+    "readme.md",
+    "README.txt",
+    "readme.txt",
+    "README",
+];
+
+/// READMEs bigger than this are only listed, not shown.
+const MAX_README_SIZE: u64 = 1024 * 1024;
+
+/// How much of a text file `?preview=` shows.
+const PREVIEW_LINES: usize = 40;
+const MAX_PREVIEW_BYTES: u64 = 16 * 1024;
+
+/// Width of the thumbnail `?preview=` shows for images.
+const PREVIEW_WIDTH: u32 = 480;
+
+/// Limits on `?search=`, which keep a search of a huge tree responsive.
+const MAX_SEARCH_RESULTS: usize = 200;
+const MAX_SEARCH_DEPTH: usize = 16;
+
+/// How many files `/_recent` lists unless `?limit=` says otherwise, and the
+/// most it lists at all.
+const DEFAULT_RECENT: usize = 50;
+const MAX_RECENT: usize = 500;
+/// Files looked at by `/_recent` before it stops walking, across all
+/// mounts, so a huge tree can't tie up a worker.
+const MAX_RECENT_SCANNED: usize = 100_000;
+
+/// Most entries `?per=` can ask for on one page of a listing.
+const MAX_PAGE_SIZE: usize = 10_000;
+
+pub fn serve_directory(
+    path: &Path,
+    mount: &Mount,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+) {
+    if archive::wants_zip(request) {
+        archive::serve_zip(path, mount, request, config, response);
+        return;
+    }
+
+    if let Some(term) = request
+        .query_param("search")
+        .filter(|term| !term.is_empty())
+    {
+        let (results, truncated) = search(path, &term, mount, config);
+        match Format::from_request(request) {
+            Format::Json => send_json(response, request, VARY_USER_AGENT, &results),
+            Format::Text => send_text(response, request, VARY_USER_AGENT, &results),
+            Format::Html => {
+                let html = render_search(path, mount, &term, &results, truncated);
+                html::send(response, request, "text/html", VARY_USER_AGENT, html);
+            }
+        }
+        return;
+    }
+
+    let sort = SortOrder::from_request(request);
+    let mut entries: Vec<Entry> = WalkDir::new(path)
+        .min_depth(1)
+        .max_depth(1)
+        .into_iter()
+        .flatten()
+        .filter(|entry| !is_hidden(entry, mount, config))
+        .map(|entry| Entry::new(&entry, mount))
+        .collect();
+    // `?q=` narrows the listing down to names containing the term
+    let filter = request.query_param("q").unwrap_or_default();
+    if !filter.is_empty() {
+        let term = filter.to_lowercase();
+        entries.retain(|entry| entry.name.to_lowercase().contains(&term));
+    }
+    sort.apply(&mut entries);
+
+    // Revisiting an unchanged directory doesn't need the listing again
+    let modified = last_modified(path, &entries);
+    let headers = match modified.and_then(response::http_date) {
+        Some(last_modified) => format!(
+            "{}Last-Modified: {}\r\nCache-Control: no-cache\r\n",
+            VARY_USER_AGENT, last_modified
+        ),
+        None => VARY_USER_AGENT.to_string(),
+    };
+    if modified.is_some_and(|modified| request.is_unchanged(modified, None)) {
+        response.write_head(304, &headers).unwrap_or(());
+        return;
+    }
+
+    match Format::from_request(request) {
+        Format::Json => send_json(response, request, &headers, &entries),
+        Format::Text => send_text(response, request, &headers, &entries),
+        Format::Html => {
+            // `?noreadme=1` leaves out the directory's README
+            let readme = if request.query_param("noreadme").as_deref() == Some("1") {
+                None
+            } else {
+                readme(path, mount, config)
+            };
+            // `?preview=name` shows the start of one of the files alongside
+            let preview = request
+                .query_param("preview")
+                .and_then(|name| preview(path, &name, &entries, mount, config));
+            let sections = Sections {
+                preview,
+                readme,
+                tools: true,
+                page: Page::from_request(request, config, entries.len()),
+            };
+            let html = render_html(path, mount, &sort, &filter, &entries, sections, config);
+            html::send(response, request, "text/html", &headers, html);
+        }
+    }
+}
+
+/// Lists a directory inside an archive mount, given by its normalized path
+/// in the archive. The entries can be filtered and sorted, but there's no
+/// search, preview or README.
+pub fn serve_bundle_directory(
+    bundle: &Bundle,
+    dir: &str,
+    mount: &Mount,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+) {
+    let sort = SortOrder::from_request(request);
+    let mut entries: Vec<Entry> = bundle
+        .children(dir)
+        .filter(|(name, node)| {
+            !bundle::is_hidden(name, node.is_dir, config)
+                && config.within_depth(Path::new(name), node.is_dir)
+        })
+        .map(|(name, node)| Entry {
+            name: name.rsplit('/').next().unwrap_or(name).to_string(),
+            url: format!(
+                "{}/{}{}",
+                mount.url_prefix,
+                request::encode_path(name),
+                if node.is_dir { "/" } else { "" }
+            ),
+            is_dir: node.is_dir,
+            is_symlink: false,
+            has_thumbnail: false,
+            in_bundle: true,
+            size: Some(node.size),
+            modified: node.modified,
+        })
+        .collect();
+    let filter = request.query_param("q").unwrap_or_default();
+    if !filter.is_empty() {
+        let term = filter.to_lowercase();
+        entries.retain(|entry| entry.name.to_lowercase().contains(&term));
+    }
+    sort.apply(&mut entries);
+
+    match Format::from_request(request) {
+        Format::Json => send_json(response, request, VARY_USER_AGENT, &entries),
+        Format::Text => send_text(response, request, VARY_USER_AGENT, &entries),
+        Format::Html => {
+            let sections = Sections {
+                preview: None,
+                readme: None,
+                tools: false,
+                page: Page::from_request(request, config, entries.len()),
+            };
+            let path = mount.root.join(dir);
+            let html = render_html(&path, mount, &sort, &filter, &entries, sections, config);
+            html::send(response, request, "text/html", VARY_USER_AGENT, html);
+        }
+    }
+}
+
+/// Whether `entry` is left out of listings and archives, because it's a
+/// dotfile, the mount's ignore file matches it, it's on the blocklist, it's
+/// below `--max-depth`, or it's a pipe, socket or device, which can't be
+/// served. Links count as what they lead to, and broken ones are left out.
+pub fn is_hidden(entry: &DirEntry, mount: &Mount, config: &Config) -> bool {
+    let file_type = entry.file_type();
+    let is_dir = file_type.is_dir();
+    let servable = if file_type.is_symlink() {
+        // A link is opened in place of what it leads to, so that decides
+        fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_file() || metadata.is_dir())
+    } else {
+        is_dir || file_type.is_file()
+    };
+    if !servable {
+        return true;
+    }
+    let relative_path = entry
+        .path()
+        .strip_prefix(&mount.root)
+        .unwrap_or(entry.path());
+    (!config.show_hidden && entry.file_name().to_string_lossy().starts_with('.'))
+        || mount.is_ignored(entry.path(), is_dir)
+        || config.is_blocked(entry.path(), mount, is_dir)
+        || !config.within_depth(relative_path, is_dir)
+}
+
+/// The format is partly picked by `User-Agent`, so caches must key on it.
+const VARY_USER_AGENT: &str = "Vary: User-Agent\r\n";
+
+/// The ways a listing can be sent.
+enum Format {
+    Html,
+    Json,
+    /// One name per line, for command line tools.
+    Text,
+}
+
+impl Format {
+    /// Picks the format asked for with `?format=json` or `?format=text`,
+    /// falling back to the `Accept` header and then to plain text for curl
+    /// and wget, which would otherwise dump HTML on the terminal.
+    fn from_request(request: &Request) -> Format {
+        match request.query_param("format").as_deref() {
+            Some("json") => return Format::Json,
+            Some("text") => return Format::Text,
+            Some("html") => return Format::Html,
+            _ => {}
+        }
+
+        let accept = request.header("Accept").unwrap_or("");
+        if accept.contains("application/json") {
+            return Format::Json;
+        }
+        if accept.contains("text/plain") && !accept.contains("text/html") {
+            return Format::Text;
+        }
+        let user_agent = request.header("User-Agent").unwrap_or("").to_lowercase();
+        if !accept.contains("text/html")
+            && (user_agent.starts_with("curl/") || user_agent.starts_with("wget/"))
+        {
+            return Format::Text;
+        }
+        Format::Html
+    }
+}
+
+fn send_json(response: &mut Response, request: &Request, headers: &str, entries: &[Entry]) {
+    html::send(
+        response,
+        request,
+        "application/json",
+        headers,
+        render_json(entries),
+    );
+}
+
+fn send_text(response: &mut Response, request: &Request, headers: &str, entries: &[Entry]) {
+    html::send(
+        response,
+        request,
+        "text/plain; charset=utf-8",
+        headers,
+        render_text(entries),
+    );
+}
+
+/// When the server started, set by `init`.
+static STARTED: OnceLock<SystemTime> = OnceLock::new();
+
+/// Records the time the server starts at, for `last_modified`. Only the
+/// first call has any effect.
+pub fn init() {
+    let _ = STARTED.set(SystemTime::now());
+}
+
+/// When a listing of `dir` last changed: the latest of the directory's own
+/// modification time, which changes as entries come and go, and those of
+/// the entries, whose sizes and times are shown. Listings from before the
+/// server started may have been made with other options, so they never
+/// count as current.
+fn last_modified(dir: &Path, entries: &[Entry]) -> Option<SystemTime> {
+    let started = *STARTED.get_or_init(SystemTime::now);
+    let dir_modified = fs::metadata(dir)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    entries
+        .iter()
+        .filter_map(|entry| entry.modified)
+        .chain([dir_modified, started])
+        .max()
+}
+
+/// Lists entry names one per line, with directories marked by a trailing
+/// `/`.
+fn render_text(entries: &[Entry]) -> String {
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(&entry.name);
+        if entry.is_dir {
+            text.push('/');
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn render_json(entries: &[Entry]) -> String {
+    let entries: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.name,
+                "is_dir": entry.is_dir,
+                "is_symlink": entry.is_symlink,
+                "size": if entry.is_dir { None } else { entry.size },
+                "modified": entry.modified.and_then(|time| {
+                    time.duration_since(UNIX_EPOCH).ok().map(|duration| duration.as_secs())
+                }),
+            })
+        })
+        .collect();
+    serde_json::Value::Array(entries).to_string()
+}
+
+/// Lists the mount points, for requests that fall outside all of them.
+pub fn serve_mounts(mounts: &[Mount], request: &Request, response: &mut Response) {
+    let mut body = String::from("<h1>Mounted directories</h1><table>");
+    body.push_str("<tr><th>Path</th><th>Directory</th></tr>");
+    for mount in mounts {
+        body.push_str(&format!(
+            "<tr><td>📁 <a href=\"{}/\">{}/</a></td><td class=\"modified\">{}</td></tr>",
+            html::escape(&mount.url_prefix),
+            html::escape(&mount.prefix),
+            html::escape(&mount.root.display().to_string())
+        ));
+    }
+    body.push_str("</table>");
+
+    let html = html::page("Mounted directories", &body);
+    html::send(response, request, "text/html", "", html);
+}
+
+/// The parts of a listing page that depend on the request beyond the
+/// entries, with the optional ones already rendered.
+struct Sections {
+    preview: Option<String>,
+    readme: Option<String>,
+    /// Offer the ZIP download, search, previews and uploads, which only
+    /// work on real directories.
+    tools: bool,
+    /// Which of the entries to show.
+    page: Page,
+}
+
+fn render_html(
+    path: &Path,
+    mount: &Mount,
+    sort: &SortOrder,
+    filter: &str,
+    entries: &[Entry],
+    sections: Sections,
+    config: &Config,
+) -> String {
+    let root_dir = mount.root.as_path();
+    let relative_path = path.strip_prefix(root_dir).unwrap_or(path);
+    let title = if relative_path.as_os_str().is_empty() {
+        format!("Directory listing for {}", root_dir.display())
+    } else {
+        format!(
+            "Directory listing for {}/{}",
+            root_dir.display(),
+            relative_path.display()
+        )
+    };
+
+    let mut table = String::from("<table>");
+    table.push_str(&format!(
+        "<tr><th>{}</th><th class=\"size\">{}</th><th>{}</th></tr>",
+        sort.header_link(SortKey::Name, "Name", filter),
+        sort.header_link(SortKey::Size, "Size", filter),
+        sort.header_link(SortKey::Modified, "Modified", filter)
+    ));
+
+    // Always display "Go back up a directory" even at root
+    let root_url = format!("{}/", config.base_url());
+    let parent_url: String = if path == root_dir {
+        root_url // At root, link just reloads the root
+    } else if let Some(parent) = path.parent() {
+        if parent.starts_with(root_dir) {
+            let parent_display = parent
+                .strip_prefix(root_dir)
+                .unwrap_or(parent)
+                .display()
+                .to_string();
+            // Directories are linked with the slash they'd be redirected to
+            let slash = if parent_display.is_empty() { "" } else { "/" };
+            format!(
+                "{}/{}{}",
+                mount.url_prefix,
+                request::encode_path(&parent_display),
+                slash
+            )
+        } else {
+            root_url // If for any reason parent is outside root, go back to "/"
+        }
+    } else {
+        root_url // Fallback in case of unexpected errors
+    };
+
+    table.push_str(&format!(
+        "<tr><td colspan=\"3\"><a href=\"{}\">⬆️ Go back up a directory</a></td></tr>",
+        parent_url
+    ));
+
+    // List the entries on this page
+    for entry in &entries[sections.page.range(entries.len())] {
+        table.push_str(&render_row(entry, sections.tools));
+    }
+    table.push_str("</table>");
+
+    let upload = if sections.tools && config.writable && config.may_change(path, mount, false) {
+        concat!(
+            "<form class=\"upload\" method=\"post\" enctype=\"multipart/form-data\">",
+            "<input type=\"file\" name=\"file\" multiple required> ",
+            "<button type=\"submit\">Upload</button>",
+            "</form>"
+        )
+    } else {
+        ""
+    };
+    let breadcrumb = breadcrumbs(mount, relative_path);
+    let forms = if sections.tools {
+        format!("{}{}", filter_form(sort, filter), search_form(""))
+    } else {
+        filter_form(sort, filter)
+    };
+    let totals = totals(entries);
+    let pages = sections.page.links(sort, filter);
+    let preview = sections.preview.unwrap_or_default();
+    let readme = sections.readme.unwrap_or_default();
+
+    if let Some(template) = &config.template {
+        return html::fill(
+            template,
+            &[
+                ("title", &html::escape(&title)),
+                ("style", html::STYLE),
+                ("breadcrumb", &breadcrumb),
+                ("search", &forms),
+                ("entries", &table),
+                ("totals", &totals),
+                ("pages", &pages),
+                ("upload", upload),
+                ("preview", &preview),
+                ("readme", &readme),
+            ],
+        );
+    }
+
+    let mut body = format!("<h1>{}</h1>", html::escape(&title));
+    body.push_str(&breadcrumb);
+    if sections.tools {
+        body.push_str("<p><a href=\"?download=zip\">⬇️ Download as ZIP</a></p>");
+    }
+    body.push_str(&forms);
+    body.push_str(&preview);
+    body.push_str(&table);
+    body.push_str(&pages);
+    body.push_str(&totals);
+    body.push_str(upload);
+    body.push_str(&readme);
+
+    html::page(&title, &body)
+}
+
+/// A table row linking to `entry`, with its size and modification time, and
+/// a link to preview it if `previews` is set.
+fn render_row(entry: &Entry, previews: bool) -> String {
+    let entry_type = if entry.is_dir {
+        "📁 ".to_string()
+    } else if entry.has_thumbnail {
+        format!(
+            "<img class=\"thumb\" src=\"{}?thumb=1&amp;w=64\" alt=\"\" loading=\"lazy\"> ",
+            entry.url
+        )
+    } else {
+        "📄 ".to_string()
+    };
+    // The exact byte count shows on hover
+    let size = match entry.size {
+        Some(size) if !entry.is_dir => format!(
+            "<td class=\"size\" title=\"{} bytes\">{}</td>",
+            size,
+            format_size(size)
+        ),
+        _ => "<td class=\"size\">-</td>".to_string(),
+    };
+    let modified = entry
+        .modified
+        .map(format_timestamp)
+        .unwrap_or_else(|| "-".to_string());
+
+    let symlink = if entry.is_symlink {
+        " <span class=\"symlink\" title=\"Symbolic link\">🔗</span>"
+    } else {
+        ""
+    };
+    // A plain link to the file's own directory, so previews work from
+    // search results and without JavaScript
+    let preview = match entry.url.rsplit_once('/') {
+        Some((dir_url, name)) if previews && !entry.is_dir => format!(
+            " <a class=\"preview\" href=\"{}/?preview={}\" title=\"Preview\">👁</a>",
+            dir_url, name
+        ),
+        _ => String::new(),
+    };
+
+    format!(
+        "<tr><td>{}<a href=\"{}\">{}</a>{}{}</td>{}<td class=\"modified\">{}</td></tr>",
+        entry_type,
+        entry.url,
+        html::escape(&entry.name),
+        symlink,
+        preview,
+        size,
+        modified
+    )
+}
+
+/// The directory's README, rendered to show under the listing. Markdown is
+/// turned into HTML and plain text is shown as-is. READMEs that are hidden,
+/// ignored, too big, or symlinks that can't be followed are left out.
+fn readme(dir: &Path, mount: &Mount, config: &Config) -> Option<String> {
+    let path = README_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|candidate| candidate.is_file())?;
+    if mount.is_ignored(&path, false)
+        || !mount.allows_link(&path, config.follow_symlinks)
+        || path.metadata().ok()?.len() > MAX_README_SIZE
+    {
+        return None;
+    }
+
+    let source = String::from_utf8_lossy(&fs::read(&path).ok()?).into_owned();
+    let contents = if markdown::is_markdown(&path) {
+        markdown::to_html(&source)
+    } else {
+        format!("<pre>{}</pre>", html::escape(&source))
+    };
+    Some(format!("<section class=\"readme\">{}</section>", contents))
+}
+
+/// A panel showing the start of the file called `name` in `dir`, or a
+/// thumbnail for images. Only files in the listing itself can be previewed,
+/// so hidden and ignored ones stay out of reach.
+fn preview(
+    dir: &Path,
+    name: &str,
+    entries: &[Entry],
+    mount: &Mount,
+    config: &Config,
+) -> Option<String> {
+    let entry = entries
+        .iter()
+        .find(|entry| entry.name == name && !entry.is_dir)?;
+    let path = dir.join(&entry.name);
+    // Opening a FIFO would block the worker, so like `serve_file` only
+    // regular files are read, wherever a link leads
+    if !mount.allows_link(&path, config.follow_symlinks)
+        || !fs::metadata(&path).is_ok_and(|metadata| metadata.is_file())
+    {
+        return None;
+    }
+
+    let contents = if entry.has_thumbnail {
+        format!(
+            "<img src=\"{}?thumb=1&amp;w={}\" alt=\"\">",
+            entry.url, PREVIEW_WIDTH
+        )
+    } else {
+        let mut start = Vec::new();
+        fs::File::open(&path)
+            .ok()?
+            .take(MAX_PREVIEW_BYTES)
+            .read_to_end(&mut start)
+            .ok()?;
+        if start.contains(&0) {
+            "<p>No preview available for binary files.</p>".to_string()
+        } else {
+            let text = String::from_utf8_lossy(&start);
+            let lines: Vec<&str> = text.lines().take(PREVIEW_LINES).collect();
+            format!("<pre>{}</pre>", html::escape(&lines.join("\n")))
+        }
+    };
+    Some(format!(
+        "<section class=\"preview\"><h2>{}</h2>{}<p><a href=\"{}\">Open</a> · <a href=\"?\">Close</a></p></section>",
+        html::escape(&entry.name),
+        contents,
+        entry.url
+    ))
+}
+
+/// The files in an archive mount that `/_recent` lists, leaving out the
+/// same ones its listings do.
+fn recent_in_bundle<'a>(
+    bundle: &'a Bundle,
+    mount: &'a Mount,
+    config: &'a Config,
+) -> impl Iterator<Item = Entry> + 'a {
+    bundle
+        .files()
+        .filter(move |(name, _)| {
+            !bundle::is_hidden(name, false, config)
+                && config.within_depth(Path::new(name), false)
+                && name.split('/').count() <= MAX_SEARCH_DEPTH
+        })
+        .map(|(name, node)| Entry {
+            name: name.to_string(),
+            url: format!("{}/{}", mount.url_prefix, request::encode_path(name)),
+            is_dir: false,
+            is_symlink: false,
+            has_thumbnail: false,
+            in_bundle: true,
+            size: Some(node.size),
+            modified: node.modified,
+        })
+}
+
+/// A summary of the files listed and their combined size. Only the entries
+/// themselves are counted, not what's inside subdirectories.
+fn totals(entries: &[Entry]) -> String {
+    let files: Vec<&Entry> = entries.iter().filter(|entry| !entry.is_dir).collect();
+    let folders = entries.len() - files.len();
+    let bytes: u64 = files.iter().filter_map(|entry| entry.size).sum();
+    let plural = |count: usize, noun: &str| {
+        format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+    };
+    format!(
+        "<p class=\"totals\">{}, {}, <span title=\"{} bytes\">{}</span> in total</p>",
+        plural(files.len(), "file"),
+        plural(folders, "folder"),
+        bytes,
+        format_size(bytes)
+    )
+}
+
+/// Finds entries anywhere below `path` whose names contain `term`, for
+/// `?search=`. Each result is named by its path relative to `path`.
+/// Returns the matches and whether the search stopped at the result cap.
+fn search(path: &Path, term: &str, mount: &Mount, config: &Config) -> (Vec<Entry>, bool) {
+    let term = term.to_lowercase();
+    let mut matches = WalkDir::new(path)
+        .min_depth(1)
+        .max_depth(MAX_SEARCH_DEPTH)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !is_hidden(entry, mount, config))
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .to_lowercase()
+                .contains(&term)
+        })
+        .map(|entry| {
+            let mut found = Entry::new(&entry, mount);
+            let relative_path = entry.path().strip_prefix(path).unwrap_or(entry.path());
+            found.name = relative_path.to_string_lossy().into_owned();
+            found
+        });
+
+    let results: Vec<Entry> = matches.by_ref().take(MAX_SEARCH_RESULTS).collect();
+    let truncated = matches.next().is_some();
+    (results, truncated)
+}
+
+/// Lists the most recently modified files under every mount, newest first,
+/// with paths relative to the server's root. The walk skips hidden and
+/// ignored files and stops at the same depth as `?search=`.
+pub fn serve_recent(request: &Request, config: &Config, response: &mut Response) {
+    let limit = request
+        .query_param("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_RECENT)
+        .min(MAX_RECENT);
+
+    let mut files: Vec<Entry> = config
+        .mounts
+        .iter()
+        .flat_map(|mount| -> Box<dyn Iterator<Item = Entry>> {
+            match &mount.bundle {
+                Some(bundle) => Box::new(recent_in_bundle(bundle, mount, config)),
+                None => Box::new(
+                    WalkDir::new(&mount.root)
+                        .min_depth(1)
+                        .max_depth(MAX_SEARCH_DEPTH)
+                        .into_iter()
+                        .filter_entry(|entry| !is_hidden(entry, mount, config))
+                        .flatten()
+                        .filter(|entry| entry.file_type().is_file())
+                        .map(|entry| Entry::new(&entry, mount)),
+                ),
+            }
+        })
+        .take(MAX_RECENT_SCANNED)
+        .map(|mut file| {
+            // Files are named by their whole path, mount included
+            let path = file.url.strip_prefix(config.base_url().as_str());
+            file.name = path
+                .and_then(|path| request::decode_path(path.trim_start_matches('/')))
+                .unwrap_or_else(|| file.url.clone());
+            file
+        })
+        .collect();
+    files.sort_by_key(|file| Reverse(file.modified));
+    files.truncate(limit);
+
+    match Format::from_request(request) {
+        Format::Json => send_json(response, request, VARY_USER_AGENT, &files),
+        Format::Text => send_text(response, request, VARY_USER_AGENT, &files),
+        Format::Html => {
+            let title = "Recently modified files";
+            let mut body = format!("<h1>{}</h1>", title);
+            if files.is_empty() {
+                body.push_str("<p>No files found.</p>");
+            } else {
+                body.push_str(
+                    "<table><tr><th>Path</th><th class=\"size\">Size</th><th>Modified</th></tr>",
+                );
+                for file in &files {
+                    body.push_str(&render_row(file, !file.in_bundle));
+                }
+                body.push_str("</table>");
+            }
+            let html = html::page(title, &body);
+            html::send(response, request, "text/html", VARY_USER_AGENT, html);
+        }
+    }
+}
+
+fn render_search(
+    path: &Path,
+    mount: &Mount,
+    term: &str,
+    results: &[Entry],
+    truncated: bool,
+) -> String {
+    let root_dir = mount.root.as_path();
+    let relative_path = path.strip_prefix(root_dir).unwrap_or(path);
+    let title = format!("Search results for \"{}\"", term);
+
+    let mut body = format!("<h1>{}</h1>", html::escape(&title));
+    body.push_str(&breadcrumbs(mount, relative_path));
+    body.push_str(&search_form(term));
+    if results.is_empty() {
+        body.push_str("<p>No matches found.</p>");
+    } else {
+        if truncated {
+            body.push_str(&format!(
+                "<p>Showing the first {} matches.</p>",
+                MAX_SEARCH_RESULTS
+            ));
+        }
+        body.push_str("<table><tr><th>Path</th><th class=\"size\">Size</th><th>Modified</th></tr>");
+        for entry in results {
+            body.push_str(&render_row(entry, true));
+        }
+        body.push_str("</table>");
+    }
+
+    html::page(&title, &body)
+}
+
+/// The search box for finding entries in all subdirectories.
+fn search_form(term: &str) -> String {
+    format!(
+        concat!(
+            "<form class=\"filter\" method=\"get\">",
+            "<input type=\"search\" name=\"search\" value=\"{}\" placeholder=\"Search subdirectories\"> ",
+            "<button type=\"submit\">Search</button>",
+            "</form>"
+        ),
+        html::escape(term)
+    )
+}
+
+/// The search box for filtering the listing by name, which keeps the
+/// current sort order.
+fn filter_form(sort: &SortOrder, filter: &str) -> String {
+    format!(
+        concat!(
+            "<form class=\"filter\" method=\"get\">",
+            "<input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"Filter by name\"> ",
+            "<input type=\"hidden\" name=\"sort\" value=\"{}\">",
+            "<input type=\"hidden\" name=\"order\" value=\"{}\">",
+            "<button type=\"submit\">Filter</button>",
+            "</form>"
+        ),
+        html::escape(filter),
+        sort.key.as_str(),
+        if sort.descending { "desc" } else { "asc" }
+    )
+}
+
+/// A trail of links to every ancestor of the directory, like
+/// `root / docs / api`, with the current directory last and unlinked.
+fn breadcrumbs(mount: &Mount, relative_path: &Path) -> String {
+    let root_label = if mount.prefix.is_empty() {
+        "root"
+    } else {
+        mount.prefix.trim_start_matches('/')
+    };
+
+    let mut url = mount.url_prefix.clone();
+    let mut crumbs = vec![(format!("{}/", url), root_label.to_string())];
+    for component in relative_path.components() {
+        let name = component.as_os_str().to_string_lossy();
+        url.push('/');
+        url.push_str(&request::encode_path(&name));
+        crumbs.push((format!("{}/", url), name.into_owned()));
+    }
+
+    let last = crumbs.len() - 1;
+    let links: Vec<String> = crumbs
+        .iter()
+        .enumerate()
+        .map(|(index, (url, name))| {
+            if index == last {
+                format!("<span>{}</span>", html::escape(name))
+            } else {
+                format!(
+                    "<a href=\"{}\">{}</a>",
+                    html::escape(url),
+                    html::escape(name)
+                )
+            }
+        })
+        .collect();
+    format!("<nav class=\"breadcrumbs\">{}</nav>", links.join(" / "))
+}
+
+/// A directory entry with the details shown in the listing.
+struct Entry {
+    name: String,
+    /// Link to the entry, already URL-encoded.
+    url: String,
+    is_dir: bool,
+    is_symlink: bool,
+    /// Whether the listing shows a preview of the image in place of an icon.
+    has_thumbnail: bool,
+    /// Whether the entry is inside an archive, where there are no previews.
+    in_bundle: bool,
+    size: Option<u64>,
+    modified: Option<SystemTime>,
+}
+
+impl Entry {
+    fn new(entry: &DirEntry, mount: &Mount) -> Entry {
+        let entry_path = entry.path();
+        let relative_path = entry_path.strip_prefix(&mount.root).unwrap_or(entry_path);
+        let metadata = entry.metadata().ok();
+        let is_dir = entry_path.is_dir();
+        Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            url: format!(
+                "{}/{}{}",
+                mount.url_prefix,
+                request::encode_path(&relative_path.to_string_lossy()),
+                if is_dir { "/" } else { "" }
+            ),
+            is_dir,
+            is_symlink: entry.path_is_symlink(),
+            has_thumbnail: entry_path.is_file() && thumbnail::is_supported(entry_path),
+            in_bundle: false,
+            size: metadata.as_ref().map(|metadata| metadata.len()),
+            modified: metadata.and_then(|metadata| metadata.modified().ok()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+        }
+    }
+}
+
+/// The listing order requested through `?sort=<key>&order=<asc|desc>`.
+struct SortOrder {
+    key: SortKey,
+    descending: bool,
+}
+
+impl SortOrder {
+    fn from_request(request: &Request) -> SortOrder {
+        let mut order = SortOrder {
+            key: SortKey::Name,
+            descending: false,
+        };
+        for (name, value) in request.query_params() {
+            match (name.as_str(), value.as_str()) {
+                ("sort", "name") => order.key = SortKey::Name,
+                ("sort", "size") => order.key = SortKey::Size,
+                ("sort", "modified") => order.key = SortKey::Modified,
+                ("order", "desc") => order.descending = true,
+                ("order", "asc") => order.descending = false,
+                _ => {}
+            }
+        }
+        order
+    }
+
+    /// Sorts entries by the chosen column, keeping directories above files.
+    fn apply(&self, entries: &mut [Entry]) {
+        entries.sort_by(|a, b| {
+            let ordering = match self.key {
+                SortKey::Name => natural_cmp(&a.name, &b.name),
+                SortKey::Size => a
+                    .size
+                    .cmp(&b.size)
+                    .then_with(|| natural_cmp(&a.name, &b.name)),
+                SortKey::Modified => a
+                    .modified
+                    .cmp(&b.modified)
+                    .then_with(|| natural_cmp(&a.name, &b.name)),
+            };
+            let ordering = if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            b.is_dir.cmp(&a.is_dir).then(ordering)
+        });
+    }
+
+    /// A column header that sorts by `key`, flipping the order when the
+    /// listing is already sorted by it. Any filter stays applied.
+    fn header_link(&self, key: SortKey, label: &str, filter: &str) -> String {
+        let active = self.key == key;
+        let next_order = if active && !self.descending {
+            "desc"
+        } else {
+            "asc"
+        };
+        let arrow = match (active, self.descending) {
+            (true, false) => " ▲",
+            (true, true) => " ▼",
+            (false, _) => "",
+        };
+        format!(
+            "<a href=\"?sort={}&amp;order={}{}\">{}</a>{}",
+            key.as_str(),
+            next_order,
+            filter_param(filter),
+            label,
+            arrow
+        )
+    }
+}
+
+/// The `&q=` part of a link that keeps `filter` applied, escaped for HTML.
+fn filter_param(filter: &str) -> String {
+    if filter.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "&amp;q={}",
+            html::escape(&url_escape::encode_component(filter))
+        )
+    }
+}
+
+/// One page of a long listing, picked by `?page=` and sized by `?per=`,
+/// which defaults to `--page-size`.
+struct Page {
+    /// Counted from 1.
+    number: usize,
+    per: usize,
+    count: usize,
+}
+
+impl Page {
+    fn from_request(request: &Request, config: &Config, total: usize) -> Page {
+        let param = |name| {
+            request
+                .query_param(name)
+                .and_then(|value| value.parse::<usize>().ok())
+                .filter(|&value| value > 0)
+        };
+        let per = param("per").unwrap_or(config.page_size).min(MAX_PAGE_SIZE);
+        let count = total.div_ceil(per).max(1);
+        let number = param("page").unwrap_or(1).min(count);
+        Page { number, per, count }
+    }
+
+    /// Which of `total` entries are on the page.
+    fn range(&self, total: usize) -> Range<usize> {
+        let start = (self.number - 1) * self.per;
+        start.min(total)..(start + self.per).min(total)
+    }
+
+    /// Links to the previous and next pages, keeping the sort order and
+    /// filter. Empty when everything fits on one page.
+    fn links(&self, sort: &SortOrder, filter: &str) -> String {
+        if self.count == 1 {
+            return String::new();
+        }
+        let link = |number: usize, label: &str| {
+            format!(
+                "<a href=\"?sort={}&amp;order={}{}&amp;per={}&amp;page={}\">{}</a>",
+                sort.key.as_str(),
+                if sort.descending { "desc" } else { "asc" },
+                filter_param(filter),
+                self.per,
+                number,
+                label
+            )
+        };
+        let previous = if self.number > 1 {
+            link(self.number - 1, "⬅️ Previous")
+        } else {
+            String::new()
+        };
+        let next = if self.number < self.count {
+            link(self.number + 1, "Next ➡️")
+        } else {
+            String::new()
+        };
+        format!(
+            "<nav class=\"pages\">{} <span>Page {} of {}</span> {}</nav>",
+            previous, self.number, self.count, next
+        )
+    }
+}
+
+/// Compares names the way file managers do: ignoring case, and with runs of
+/// digits compared by value, so `file2` comes before `file10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let (x, y) = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(&x), Some(&y)) => (x, y),
+        };
+
+        let ordering = if x.is_ascii_digit() && y.is_ascii_digit() {
+            let x = take_number(&mut a_chars);
+            let y = take_number(&mut b_chars);
+            // Without leading zeros, the longer run of digits is the bigger
+            // number
+            x.len().cmp(&y.len()).then_with(|| x.cmp(&y))
+        } else {
+            a_chars.next();
+            b_chars.next();
+            x.to_lowercase().cmp(y.to_lowercase())
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Consumes a run of digits, returning it without leading zeros.
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        if !(digits.is_empty() && c == '0') {
+            digits.push(c);
+        }
+    }
+    digits
+}
+
+/// Formats a byte count with binary units, e.g. `1.4 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Formats a timestamp as `YYYY-MM-DD HH:MM` in UTC.
+fn format_timestamp(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, _) = utc_fields(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year, month, day, hour, minute
+    )
+}
+
+/// Splits a timestamp into its UTC year, month, day, hour, minute and second.
+pub fn utc_fields(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days conversion for the proleptic Gregorian calendar
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        year,
+        month as u32,
+        day as u32,
+        (secs_of_day / 3600) as u32,
+        (secs_of_day % 3600 / 60) as u32,
+        (secs_of_day % 60) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn breadcrumbs_are_escaped_like_row_links() {
+        let root = TempDir::new("breadcrumbs");
+        let config = Config::from_args([root.path().display().to_string()].into_iter()).unwrap();
+        let html = breadcrumbs(&config.mounts[0], Path::new("my docs/café/a+b&c"));
+        assert!(html.contains("<a href=\"/my%20docs/\">my docs</a>"));
+        assert!(html.contains("<a href=\"/my%20docs/caf%C3%A9/\">café</a>"));
+        assert!(html.ends_with("<span>a+b&amp;c</span></nav>"));
+    }
+
+    #[test]
+    fn names_sort_naturally() {
+        let mut names = vec![
+            "file10.txt",
+            "File2.txt",
+            "file1.txt",
+            "file02.txt",
+            "file",
+            "b",
+            "A",
+            "file1a.txt",
+            "99999999999999999999999.txt",
+            "100000000000000000000000.txt",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            [
+                "99999999999999999999999.txt",
+                "100000000000000000000000.txt",
+                "A",
+                "b",
+                "file",
+                "file1.txt",
+                "file1a.txt",
+                "File2.txt",
+                "file02.txt",
+                "file10.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_equal_but_for_case_or_zeros_still_have_an_order() {
+        assert_eq!(natural_cmp("a", "a"), Ordering::Equal);
+        assert_ne!(natural_cmp("a", "A"), Ordering::Equal);
+        assert_ne!(natural_cmp("file2", "file02"), Ordering::Equal);
+        assert_eq!(natural_cmp("A", "a"), natural_cmp("a", "A").reverse());
+    }
+
+    fn page(query: &str, page_size: usize, total: usize) -> (usize, usize, Range<usize>) {
+        let root = TempDir::new("page");
+        let args = [
+            "--page-size".to_string(),
+            page_size.to_string(),
+            root.path().display().to_string(),
+        ];
+        let config = Config::from_args(args.into_iter()).unwrap();
+        let head = format!("GET /{} HTTP/1.1\r\n\r\n", query);
+        let request = Request::parse(&head).unwrap();
+        let page = Page::from_request(&request, &config, total);
+        (page.number, page.count, page.range(total))
+    }
+
+    #[test]
+    fn pages_split_the_listing() {
+        assert_eq!(page("", 10, 25), (1, 3, 0..10));
+        assert_eq!(page("?page=2", 10, 25), (2, 3, 10..20));
+        assert_eq!(page("?page=3", 10, 25), (3, 3, 20..25));
+        assert_eq!(page("?page=2", 10, 20), (2, 2, 10..20));
+        assert_eq!(page("?per=5&page=4", 10, 25), (4, 5, 15..20));
+    }
+
+    #[test]
+    fn out_of_range_pages_are_clamped() {
+        assert_eq!(page("?page=9", 10, 25), (3, 3, 20..25));
+        assert_eq!(page("?page=0", 10, 25), (1, 3, 0..10));
+        assert_eq!(page("?page=-1&per=x", 10, 25), (1, 3, 0..10));
+        assert_eq!(page("?per=0", 10, 25), (1, 3, 0..10));
+        assert_eq!(page("?page=18446744073709551616", 10, 25), (1, 3, 0..10));
+        assert_eq!(page("?per=1000000", 10, 25), (1, 1, 0..25));
+    }
+
+    #[test]
+    fn an_empty_listing_has_one_empty_page() {
+        assert_eq!(page("", 10, 0), (1, 1, 0..0));
+        assert_eq!(page("?page=5", 10, 0), (1, 1, 0..0));
+    }
+
+    #[test]
+    fn page_sizes_are_capped() {
+        let per = |total| page("?per=99999999", 10, total).1;
+        assert_eq!(per(MAX_PAGE_SIZE), 1);
+        assert_eq!(per(MAX_PAGE_SIZE + 1), 2);
+    }
+}
@@ -0,0 +1,151 @@
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// How much gets logged. Errors are always logged.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    /// Errors only.
+    Quiet,
+    /// One line per request.
+    Normal,
+    /// Also request headers and how paths were resolved.
+    Verbose,
+}
+
+static SETTINGS: OnceLock<(LogFormat, LogLevel)> = OnceLock::new();
+
+/// Where access log lines go instead of stdout, with `--log-file`. Each line
+/// is written straight to the file, so nothing is lost if the server dies.
+static FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Sets the format and level used for every log line. Only the first call
+/// has any effect; until then lines are written as text at the normal level.
+pub fn init(format: LogFormat, level: LogLevel) {
+    let _ = SETTINGS.set((format, level));
+}
+
+/// Sends access log lines to the end of the file at `path`, creating it if
+/// needed. Only the first call has any effect.
+pub fn open_file(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+fn format() -> LogFormat {
+    SETTINGS
+        .get()
+        .map_or(LogFormat::Text, |(format, _)| *format)
+}
+
+fn level() -> LogLevel {
+    SETTINGS.get().map_or(LogLevel::Normal, |(_, level)| *level)
+}
+
+/// Whether debugging details are being logged, so callers can skip
+/// building messages nobody will see.
+pub fn is_verbose() -> bool {
+    level() == LogLevel::Verbose
+}
+
+/// One handled request, as it appears in the access log.
+pub struct AccessEntry<'a> {
+    pub client: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub bytes_sent: u64,
+    pub elapsed: Duration,
+    pub request_id: &'a str,
+}
+
+/// Logs a handled request to stdout, or to the log file if there is one.
+pub fn access(entry: &AccessEntry) {
+    if level() == LogLevel::Quiet {
+        return;
+    }
+    let elapsed_ms = entry.elapsed.as_secs_f64() * 1000.0;
+    let time = httpdate::fmt_http_date(SystemTime::now());
+
+    let line = match format() {
+        LogFormat::Text => format!(
+            "[{}] {} \"{} {}\" {} {} {:.2}ms {}",
+            time,
+            entry.client,
+            entry.method,
+            entry.path,
+            entry.status,
+            entry.bytes_sent,
+            elapsed_ms,
+            entry.request_id
+        ),
+        LogFormat::Json => json!({
+            "time": time,
+            "client": entry.client,
+            "method": entry.method,
+            "path": entry.path,
+            "status": entry.status,
+            "bytes_sent": entry.bytes_sent,
+            "elapsed_ms": elapsed_ms,
+            "request_id": entry.request_id,
+        })
+        .to_string(),
+    };
+
+    match FILE.get() {
+        Some(file) => {
+            // One write per line keeps lines from different workers apart
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = file.write_all(format!("{}\n", line).as_bytes()) {
+                log_message("error", &format!("Failed to write to the log file: {}", e));
+            }
+        }
+        None => println!("{}", line),
+    }
+}
+
+/// Logs a server-side problem to stderr.
+pub fn error(message: &str) {
+    log_message("error", message);
+}
+
+/// Logs something worth knowing that isn't a problem to stderr, unless
+/// `--quiet` is given.
+pub fn info(message: &str) {
+    if level() != LogLevel::Quiet {
+        log_message("info", message);
+    }
+}
+
+/// Logs a debugging detail to stderr, with `--verbose` only.
+pub fn debug(message: &str) {
+    if is_verbose() {
+        log_message("debug", message);
+    }
+}
+
+fn log_message(level: &str, message: &str) {
+    let time = httpdate::fmt_http_date(SystemTime::now());
+
+    match format() {
+        LogFormat::Text => eprintln!("[{}] {}: {}", time, level, message),
+        LogFormat::Json => eprintln!(
+            "{}",
+            json!({
+                "time": time,
+                "level": level,
+                "message": message,
+            })
+        ),
+    }
+}
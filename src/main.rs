@@ -1,241 +1,2043 @@
-use infer;
+mod acl;
+mod archive;
+mod auth;
+mod blocklist;
+mod bundle;
+mod cache;
+mod compress;
+mod config;
+mod highlight;
+mod html;
+mod listing;
+mod logger;
+mod markdown;
+mod metrics;
+mod mime;
+mod range;
+mod request;
+mod response;
+mod stream;
+#[cfg(test)]
+mod testing;
+mod thread_pool;
+mod throttle;
+mod thumbnail;
+mod tls;
+mod upload;
+mod watch;
+
+use bundle::Bundle;
+use cache::Contents;
+use config::{Config, Mount, USAGE};
+use logger::AccessEntry;
+use range::ByteRange;
+use request::{Body, ReadError, Request};
+use response::Response;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use serde_json::json;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::hash_map::RandomState;
 use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 use std::path::{Path, PathBuf};
-use url_escape::decode;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stream::Stream;
+use thread_pool::ThreadPool;
+use upload::UploadError;
 use walkdir::WalkDir;
 
+/// Size of the buffer used when streaming files to the client.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long an idle keep-alive connection waits for its next request.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests served on one connection before the server closes it.
+const MAX_KEEP_ALIVE_REQUESTS: usize = 100;
+
+/// How long shutdown waits for in-flight connections to finish.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Icon sent for `/favicon.ico` when the served directory doesn't have one.
+const FAVICON: &[u8] = include_bytes!("../assets/favicon.ico");
+
+/// Where the capability document is served.
+const META_PATH: &str = "/_meta";
+
+/// Where `--recent` lists the most recently modified files across all mounts.
+const RECENT_PATH: &str = "/_recent";
+
+/// Where `--metrics` serves its counters.
+const METRICS_PATH: &str = "/_metrics";
+
+/// Why pipes, sockets and devices are refused.
+const NOT_REGULAR: &str = "The path is a pipe, socket or device, not a file the server can send.";
+
+/// Why paths below `--max-depth` are refused.
+const TOO_DEEP: &str = "The path is deeper in the tree than the server lets clients browse.";
+
+/// How long clients turned away by `--max-connections` are asked to wait,
+/// and how long writing them that answer may take.
+const RETRY_AFTER_SECS: u64 = 1;
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Set once a shutdown signal arrives.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Connections currently being served.
+static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let root_dir = if args.len() > 1 {
-        PathBuf::from(&args[1])
-    } else {
-        env::current_dir().expect("Failed to get current directory")
+    let config = match Config::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    logger::init(config.log_format, config.log_level);
+    if let Some(path) = &config.log_file {
+        if let Err(e) = logger::open_file(path) {
+            logger::error(&format!("Cannot open log file {}: {}", path.display(), e));
+            process::exit(1);
+        }
+    }
+    mime::init(config.mime_types.clone());
+    listing::init();
+
+    let tls = match &config.tls {
+        Some((cert, key)) => match tls::load(cert, key) {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                logger::error(&e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    let listener = match bind(&config.bind_addr()) {
+        Ok(listener) => listener,
+        Err(e) => {
+            logger::error(&format!("Could not bind to {}: {}", config.bind_addr(), e));
+            process::exit(1);
+        }
     };
+    match listener.local_addr() {
+        Ok(addr) => println!("Server listening on {}://{}", scheme, addr),
+        Err(_) => println!("Server listening on {}://{}", scheme, config.bind_addr()),
+    }
 
-    let listener = TcpListener::bind("127.0.0.1:8080").expect("Could not bind to port 8080");
-    println!("Server listening on port 8080");
+    // Stop accepting on Ctrl-C or SIGTERM. Accepting blocks, so the handler
+    // also makes a throwaway connection to wake the loop up.
+    let wake_addr = listener.local_addr().ok().map(|mut addr| {
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        addr
+    });
+    if config.open {
+        if let Some(addr) = wake_addr {
+            open_browser(&format!("{}://{}/", scheme, addr));
+        }
+    }
+
+    if let Err(e) = ctrlc::set_handler(move || {
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+        if let Some(addr) = wake_addr {
+            let _ = TcpStream::connect(addr);
+        }
+    }) {
+        logger::error(&format!("Failed to install the shutdown handler: {}", e));
+    }
+
+    let pool = ThreadPool::new(config.threads);
+    let config = Arc::new(config);
+    if config.watch {
+        watch::spawn(Arc::clone(&config));
+    }
 
     for stream in listener.incoming() {
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            break;
+        }
         match stream {
-            Ok(stream) => handle_connection(stream, &root_dir),
-            Err(e) => eprintln!("Failed to establish a connection: {}", e),
+            Ok(stream) => {
+                if config
+                    .max_connections
+                    .is_some_and(|max| ACTIVE_CONNECTIONS.load(Ordering::SeqCst) >= max)
+                {
+                    refuse_connection(stream, tls.is_some());
+                    continue;
+                }
+                let config = Arc::clone(&config);
+                let tls = tls.clone();
+                let guard = ActiveConnection::new();
+                pool.execute(move || {
+                    let _guard = guard;
+                    // The handshake happens on the first read, in the worker
+                    if let Some(stream) = wrap_stream(stream, tls) {
+                        handle_connection(stream, &config);
+                    }
+                });
+            }
+            Err(e) => logger::error(&format!("Failed to establish a connection: {}", e)),
         }
     }
+
+    println!(
+        "Shutting down, waiting for {} active connections",
+        ACTIVE_CONNECTIONS.load(Ordering::SeqCst)
+    );
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while ACTIVE_CONNECTIONS.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let remaining = ACTIVE_CONNECTIONS.load(Ordering::SeqCst);
+    if remaining > 0 {
+        // Joining the pool would block on the stragglers, so leave them be
+        logger::error(&format!(
+            "Gave up waiting for {} active connections",
+            remaining
+        ));
+        process::exit(1);
+    }
+    drop(pool);
 }
 
-fn handle_connection(mut stream: TcpStream, root_dir: &Path) {
-    let mut buffer = [0; 1024];
-    if stream.read(&mut buffer).is_err() {
-        eprintln!("Failed to read from stream");
-        return;
+/// Binds the listening socket. An unspecified IPv6 address such as `::`
+/// is bound dual-stack, so IPv4 clients can connect too, whatever the
+/// system's default.
+fn bind(addr: &str) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        let bound = if addr.is_ipv6() && addr.ip().is_unspecified() {
+            bind_dual_stack(addr)
+        } else {
+            TcpListener::bind(addr)
+        };
+        match bound {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
     }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no addresses to bind to")))
+}
 
-    let request = String::from_utf8_lossy(&buffer[..]);
-    let path = parse_request(&request, root_dir);
+fn bind_dual_stack(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    // Match what `TcpListener::bind` does, so restarts don't wait out
+    // TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
 
-    if path.is_dir() {
-        serve_directory(&path, root_dir, &mut stream);
-    } else if path.is_file() {
-        serve_file(&path, &mut stream);
+/// Opens `url` in the default browser for `--open`. The server carries on
+/// regardless of whether that works.
+fn open_browser(url: &str) {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = process::Command::new("cmd");
+        // `start` takes the first quoted argument as a window title
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        process::Command::new("open")
     } else {
-        respond_404(&mut stream);
+        process::Command::new("xdg-open")
+    };
+    command
+        .arg(url)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null());
+
+    match command.spawn() {
+        // Reap the opener once it's done so it doesn't linger as a zombie
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => logger::error(&format!("Couldn't open a browser: {}", e)),
     }
 }
 
-fn parse_request(request: &str, root_dir: &Path) -> PathBuf {
-    let request_line = request.lines().next().expect("Failed to read request line");
-    let path = request_line
-        .split_whitespace()
-        .nth(1)
-        .expect("Failed to parse path");
-    let decoded_path = decode(path).to_string();
+/// Turns a connection away when `--max-connections` are already open. This
+/// runs on the accepting thread, so it never waits on the client: whatever
+/// part of the request has arrived is discarded, and TLS connections are
+/// just closed since answering them would take a handshake.
+fn refuse_connection(stream: TcpStream, tls: bool) {
+    if tls {
+        return;
+    }
+    let client = stream
+        .peer_addr()
+        .map(|addr| addr.ip().to_canonical().to_string())
+        .unwrap_or_else(|_| "-".to_string());
 
-    let resource = root_dir.join(decoded_path.trim_start_matches('/'));
+    // Unread data makes closing reset the connection, which can destroy
+    // the response before the client reads it
+    let mut discarded = [0; 4096];
+    if stream.set_nonblocking(true).is_ok() {
+        let _ = (&stream).read(&mut discarded);
+    }
+    if stream.set_nonblocking(false).is_err()
+        || stream.set_write_timeout(Some(REFUSAL_TIMEOUT)).is_err()
+    {
+        return;
+    }
 
-    if resource.starts_with(root_dir) {
-        resource
-    } else {
-        root_dir.to_path_buf() // Default to root directory if path is outside root
-    }
-}
-
-fn serve_directory(path: &Path, root_dir: &Path, stream: &mut TcpStream) {
-    let mut begin_html = r#"
-    <!DOCTYPE html> 
-    <html> 
-    <head> 
-        <meta charset="utf-8"> 
-        <style>
-            body { font-family: Arial, sans-serif; }
-            ul { list-style-type: none; padding: 0; }
-            li { margin: 5px 0; }
-            a { text-decoration: none; color: #0366d6; }
-            a:hover { text-decoration: underline; }
-        </style>
-    </head> 
-    <body>"#
-        .to_string();
-
-    let relative_path = path.strip_prefix(root_dir).unwrap_or(path);
-    let header = if relative_path.as_os_str().is_empty() {
-        format!("<h1>Directory listing for {}</h1>", root_dir.display())
-    } else {
-        format!(
-            "<h1>Directory listing for {}/{}</h1>",
-            root_dir.display(),
-            relative_path.display()
-        )
+    let mut stream = Stream::plain(stream);
+    let mut response = Response::new(&mut stream, false, false);
+    respond_503(&mut response);
+    log_access(&client, None, &response, Instant::now());
+}
+
+/// Counts a connection as in flight for as long as it's alive, including
+/// when its handler panics.
+struct ActiveConnection;
+
+impl ActiveConnection {
+    fn new() -> ActiveConnection {
+        ACTIVE_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+        ActiveConnection
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wraps an accepted connection in TLS when it's enabled.
+fn wrap_stream(stream: TcpStream, tls: Option<Arc<ServerConfig>>) -> Option<Stream> {
+    let Some(tls) = tls else {
+        return Some(Stream::plain(stream));
     };
-    begin_html.push_str(&header);
-
-    let mut body = String::new();
-    body.push_str("<ul>");
-
-    // Always display "Go back up a directory" even at root
-    let parent_url: String = if path == root_dir {
-        "/".to_string() // At root, link just reloads the root
-    } else if let Some(parent) = path.parent() {
-        if parent.starts_with(root_dir) {
-            let parent_display = parent
-                .strip_prefix(root_dir)
-                .unwrap_or(parent)
-                .display()
-                .to_string();
-            url_escape::encode_query(&format!("/{}", parent_display)).to_string()
+    match ServerConnection::new(tls) {
+        Ok(connection) => Some(Stream::tls(StreamOwned::new(connection, stream))),
+        Err(e) => {
+            logger::error(&format!("Failed to start a TLS session: {}", e));
+            None
+        }
+    }
+}
+
+fn handle_connection(mut stream: Stream, config: &Config) {
+    if let Some(rate) = config.rate_limit {
+        stream.set_rate_limit(rate);
+    }
+    // Dual-stack sockets see IPv4 clients as `::ffff:a.b.c.d`
+    let peer = stream.peer_addr().map(|addr| addr.ip().to_canonical()).ok();
+    let peer_client = peer.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+
+    let mut buffer = Vec::new();
+    for served in 1..=MAX_KEEP_ALIVE_REQUESTS {
+        // A fresh connection gets the full timeout to send its first request.
+        // Idle keep-alive connections are dropped sooner so they can't hold
+        // on to a worker thread.
+        let idle_timeout = if served == 1 {
+            config.timeout
         } else {
-            "/".to_string() // If for any reason parent is outside root, go back to "/"
+            KEEP_ALIVE_TIMEOUT
+        };
+
+        let head = match request::read_head(&mut stream, &mut buffer, idle_timeout, config.timeout)
+        {
+            Ok(head) => head,
+            Err(ReadError::TooLarge) => {
+                let mut response = Response::new(&mut stream, false, false);
+                respond_431(&mut response);
+                log_access(&peer_client, None, &response, Instant::now());
+                return;
+            }
+            Err(ReadError::TimedOut { partial }) if partial || served == 1 => {
+                let mut response = Response::new(&mut stream, false, false);
+                respond_408(&mut response);
+                log_access(&peer_client, None, &response, Instant::now());
+                return;
+            }
+            Err(ReadError::Closed) | Err(ReadError::TimedOut { .. }) => return,
+            Err(ReadError::Io(e)) => {
+                logger::error(&format!("Failed to read from {}: {}", peer_client, e));
+                return;
+            }
+        };
+
+        let started = Instant::now();
+        let request = match Request::parse(&String::from_utf8_lossy(&head)) {
+            Some(request) => request,
+            None => {
+                let mut response = Response::new(&mut stream, false, false);
+                respond_400(&mut response);
+                log_access(&peer_client, None, &response, started);
+                return;
+            }
+        };
+
+        // Behind a trusted proxy the socket only shows the proxy, and the
+        // real client is the first address it forwarded for
+        let ip = config
+            .trust_proxy
+            .then(|| request.forwarded_for())
+            .flatten()
+            .or(peer);
+        let client = ip.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+        let allowed = ip.is_some_and(|ip| config.is_allowed(ip));
+        let request_id = request.id().map_or_else(random_id, str::to_string);
+
+        if logger::is_verbose() {
+            log_request_head(&client, &request);
+        }
+
+        // Clients outside `--allow` still get their request read, so the
+        // refusal reaches them rather than a reset connection
+        if !allowed {
+            let mut response = Response::new(&mut stream, false, request.is_head());
+            response.set_request_id(&request_id);
+            respond_403(
+                &mut response,
+                config,
+                "Your address isn't allowed to access this server.",
+            );
+            log_access(&client, Some(&request), &response, started);
+            return;
+        }
+
+        // Request bodies are only understood with a Content-Length. Guessing
+        // at any other framing could read the body as the next request.
+        if request.header("Transfer-Encoding").is_some() {
+            let mut response = Response::new(&mut stream, false, request.is_head());
+            response.set_request_id(&request_id);
+            respond_501(&mut response);
+            log_access(&client, Some(&request), &response, started);
+            return;
+        }
+
+        // Refuse oversized bodies before reading any of them. The unread
+        // body is still on the connection, so it can't be reused.
+        if request
+            .content_length()
+            .is_some_and(|length| length > config.max_body)
+        {
+            let mut response = Response::new(&mut stream, false, request.is_head());
+            response.set_request_id(&request_id);
+            respond_413(&mut response);
+            log_access(&client, Some(&request), &response, started);
+            return;
+        }
+
+        // Bodies are never read, so a request with one can't be followed by
+        // another on the same connection
+        let keep_alive = request.wants_keep_alive()
+            && !request.has_body()
+            && served < MAX_KEEP_ALIVE_REQUESTS
+            && !SHUTTING_DOWN.load(Ordering::SeqCst);
+
+        // Uploads read their body straight from the connection
+        let mut body = match request.content_length() {
+            Some(length) if length > 0 => {
+                stream.set_read_timeout(Some(config.timeout)).unwrap_or(());
+                Body::new(mem::take(&mut buffer), length)
+            }
+            _ => Body::empty(),
+        };
+
+        let mut response = Response::new(&mut stream, keep_alive, request.is_head());
+        response.set_request_id(&request_id);
+        if request.is_http10() {
+            response.use_http10();
+        }
+        if let Some(origin) = &config.cors {
+            response.add_header("Access-Control-Allow-Origin", origin);
+            response.add_header("Access-Control-Expose-Headers", "Content-Range, ETag");
+            if origin != "*" {
+                response.add_header("Vary", "Origin");
+            }
+        }
+        handle_request(&request, &mut body, config, &mut response);
+        log_access(&client, Some(&request), &response, started);
+
+        if !response.keep_alive() {
+            return;
+        }
+    }
+}
+
+fn handle_request(request: &Request, body: &mut Body, config: &Config, response: &mut Response) {
+    // Browsers send CORS preflights without credentials, so answer them
+    // before checking authentication. They only reveal the methods allowed.
+    if request.method == "OPTIONS" {
+        respond_options(response, request, config);
+        return;
+    }
+    // `*` only means anything to OPTIONS
+    if request.target == "*" {
+        respond_400(response);
+        return;
+    }
+
+    // Load balancers probe without credentials, and the answer says nothing
+    // about the files
+    if request.path() == config.health_path && (request.method == "GET" || request.is_head()) {
+        respond_health(response);
+        return;
+    }
+
+    if let Some(auth) = &config.auth {
+        if !auth.is_authorized(request) {
+            respond_401(response);
+            return;
+        }
+    }
+
+    // Behind a proxy at `--base-path`, everything else lives under that path
+    let Some(route) = config.strip_base_path(request.path()) else {
+        respond_404(response, request, config);
+        return;
+    };
+
+    if route == META_PATH && (request.method == "GET" || request.is_head()) {
+        respond_meta(response, request, config);
+        return;
+    }
+
+    if config.metrics && route == METRICS_PATH && (request.method == "GET" || request.is_head()) {
+        respond_metrics(response, request);
+        return;
+    }
+
+    if config.recent && route == RECENT_PATH && (request.method == "GET" || request.is_head()) {
+        listing::serve_recent(request, config, response);
+        return;
+    }
+
+    // Files can only be read, unless uploads and deletion are enabled
+    let upload = config.writable && request.method == "POST";
+    let delete = config.writable && request.method == "DELETE";
+    if request.method != "GET" && request.method != "HEAD" && !upload && !delete {
+        respond_405(response, request, config);
+        return;
+    }
+
+    let (mount, rest) = match config.find_mount(route) {
+        Some(found) => found,
+        None if is_builtin_favicon(route, config) => {
+            respond_favicon(response);
+            return;
+        }
+        None => {
+            listing::serve_mounts(&config.mounts, request, response);
+            return;
         }
-    } else {
-        "/".to_string() // Fallback in case of unexpected errors
     };
 
-    body.push_str(&format!(
-        "<li><a href=\"{}\">⬆️ Go back up a directory</a></li>",
-        parent_url
+    // Archives are served read-only, straight from their entries
+    if let Some(bundle) = &mount.bundle {
+        if upload || delete {
+            respond_405(response, request, config);
+        } else {
+            serve_bundle(bundle, rest, mount, request, config, response);
+        }
+        return;
+    }
+
+    let path = match parse_request(rest, &mount.root, config.follow_symlinks) {
+        Ok(path) => path,
+        Err(PathError::Malformed) => {
+            logger::debug(&format!("{} isn't a valid URL path", request.path()));
+            respond_400(response);
+            return;
+        }
+        Err(PathError::OutsideRoot) => {
+            logger::debug(&format!(
+                "{} resolves outside {}",
+                request.path(),
+                mount.root.display()
+            ));
+            respond_403(
+                response,
+                config,
+                "The path is outside the served directory.",
+            );
+            return;
+        }
+        Err(PathError::Symlink) => {
+            logger::debug(&format!("{} goes through a symlink", request.path()));
+            respond_403(
+                response,
+                config,
+                "The path is a symbolic link, which the server doesn't follow.",
+            );
+            return;
+        }
+    };
+    logger::debug(&format!(
+        "{} resolved to {}",
+        request.path(),
+        path.display()
     ));
 
-    // List current directory entries
-    for entry in WalkDir::new(path)
-        .min_depth(1)
-        .max_depth(1)
-        .sort_by_file_name()
-    {
-        if let Ok(entry) = entry {
-            let entry_path = entry.path();
-            let relative_path = entry_path.strip_prefix(root_dir).unwrap_or(entry_path);
-            let entry_name = entry_path.file_name().unwrap_or_default().to_string_lossy();
-            let entry_type = if entry_path.is_dir() {
-                "📁 "
-            } else {
-                "📄 "
-            };
-            body.push_str(&format!(
-                "<li>{}<a href=\"/{}\">{}</a></li>",
-                entry_type,
-                url_escape::encode_query(&relative_path.to_string_lossy()),
-                entry_name
+    // Credentials stay private even with `--show-hidden`
+    if config.is_blocked(&path, mount, path.is_dir()) {
+        logger::debug(&format!("{} is on the blocklist", path.display()));
+        respond_403(
+            response,
+            config,
+            "The file looks like it holds credentials, so it isn't served.",
+        );
+        return;
+    }
+
+    // Hidden and ignored paths are treated as if they didn't exist
+    let dotfile = request::decode_segments(rest)
+        .unwrap_or_default()
+        .iter()
+        .any(|segment| segment.starts_with('.') && segment != "." && segment != "..");
+    if (dotfile && !config.show_hidden) || mount.is_ignored(&path, path.is_dir()) {
+        respond_404(response, request, config);
+        return;
+    }
+
+    // `--max-depth` keeps the lower levels of the tree out of reach
+    let real_path = path.canonicalize().unwrap_or_else(|_| path.clone());
+    let relative_path = real_path.strip_prefix(&mount.root).unwrap_or(&real_path);
+    if !config.within_depth(relative_path, path.is_dir()) {
+        respond_403(response, config, TOO_DEEP);
+        return;
+    }
+
+    if (upload || delete) && !config.may_change(&path, mount, delete) {
+        respond_403(
+            response,
+            config,
+            "Changes are only accepted in the upload directory.",
+        );
+        return;
+    }
+
+    if delete {
+        handle_delete(&path, mount, request, config, response);
+    } else if upload {
+        if path.is_dir() {
+            handle_upload(&path, mount, request, body, config, response);
+        } else {
+            respond_405(response, request, config);
+        }
+    } else if path.is_dir() && !request.path().ends_with('/') {
+        // Relative links in listings and index pages resolve against the
+        // directory only with the slash
+        respond_301(response, &directory_url(request));
+    } else if path.is_dir() {
+        match index_file(&path, mount, config) {
+            Some(index) => serve_file(&index, mount, request, config, response),
+            None => listing::serve_directory(&path, mount, request, config, response),
+        }
+    } else if path.exists() {
+        // Pipes, sockets and devices are refused by `serve_file`
+        serve_file(&path, mount, request, config, response);
+    } else if is_builtin_favicon(route, config) {
+        respond_favicon(response);
+    } else if let Some(index) = spa_fallback(&path, mount, config) {
+        serve_file(&index, mount, request, config, response);
+    } else {
+        respond_404(response, request, config);
+    }
+}
+
+/// Where a request for a directory without the trailing slash is sent, with
+/// any query kept. Leading slashes are collapsed so `//host` can't turn
+/// into a redirect to another site.
+fn directory_url(request: &Request) -> String {
+    let path = format!("/{}/", request.path().trim_start_matches('/'));
+    match request.query() {
+        "" => path,
+        query => format!("{}?{}", path, query),
+    }
+}
+
+/// With `--spa`, the mount's index file to serve for a path that doesn't
+/// exist, so client-side routes survive a reload. Paths with an extension
+/// look like missing assets and are left to 404.
+fn spa_fallback(path: &Path, mount: &Mount, config: &Config) -> Option<PathBuf> {
+    if !config.spa || path.extension().is_some() {
+        return None;
+    }
+    config
+        .index_files
+        .iter()
+        .map(|name| mount.root.join(name))
+        .find(|index| index.is_file())
+        .filter(|index| mount.allows_link(index, config.follow_symlinks))
+}
+
+/// Whether to answer with the built-in icon. A `favicon.ico` at the root
+/// always takes precedence, since this is only checked once it's missing.
+fn is_builtin_favicon(route: &str, config: &Config) -> bool {
+    config.favicon && route == "/favicon.ico"
+}
+
+fn handle_upload(
+    dir: &Path,
+    mount: &Mount,
+    request: &Request,
+    body: &mut Body,
+    config: &Config,
+    response: &mut Response,
+) {
+    let saved = upload::save(
+        dir,
+        mount,
+        request,
+        &mut body.reader(response.stream()),
+        config,
+    );
+    let error = match saved {
+        // Send browsers back to the listing, which now shows the new files
+        Ok(_) => {
+            let headers = format!("Location: {}\r\nContent-Length: 0\r\n", request.path());
+            response.write_head(303, &headers).unwrap_or(());
+            return;
+        }
+        Err(e) => e,
+    };
+
+    let status = match &error {
+        UploadError::UnsupportedType => 415,
+        UploadError::LengthRequired => 411,
+        UploadError::TooLarge => 413,
+        UploadError::Exists(_) => 409,
+        UploadError::Symlink(_) | UploadError::Hidden(_) => 403,
+        UploadError::Malformed(_) => 400,
+        UploadError::Io(e) => {
+            logger::error(&format!(
+                "Failed to save upload to {}: {}",
+                dir.display(),
+                e
             ));
+            500
+        }
+    };
+    let message = error.to_string();
+    let headers = format!(
+        "Content-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n",
+        message.len()
+    );
+    response.write_head(status, &headers).unwrap_or(());
+    response.write_all(message.as_bytes()).unwrap_or(());
+    response.flush().unwrap_or(());
+}
+
+/// Deletes the file or directory at `path`. Directories must be empty unless
+/// `?recursive=1` is given, so one request can't wipe out a whole tree by
+/// accident. The mount's root itself is never deleted.
+fn handle_delete(
+    path: &Path,
+    mount: &Mount,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            respond_delete_error(response, 404, "Nothing exists at this path");
+            return;
+        }
+        Err(e) => {
+            respond_500(
+                response,
+                config,
+                &format!("Failed to inspect {}: {}", path.display(), e),
+            );
+            return;
+        }
+    };
+    let is_root = path
+        .canonicalize()
+        .is_ok_and(|real_path| real_path == mount.root);
+    if is_root && !metadata.is_symlink() {
+        respond_delete_error(response, 403, "The served directory can't be deleted");
+        return;
+    }
+
+    // Symlinks are removed themselves, never what they point to
+    let recursive = request.query_param("recursive").as_deref() == Some("1");
+    if metadata.is_dir() && recursive && holds_hidden(path, mount, config) {
+        respond_delete_error(
+            response,
+            403,
+            "The directory holds files the server doesn't show, so it can't be deleted with them",
+        );
+        return;
+    }
+    let removed = if !metadata.is_dir() {
+        fs::remove_file(path)
+    } else if recursive {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_dir(path)
+    };
+    match removed {
+        Ok(()) => {
+            response.write_head(204, "").unwrap_or(());
+        }
+        Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => respond_delete_error(
+            response,
+            409,
+            "The directory isn't empty; add ?recursive=1 to delete it with its contents",
+        ),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            respond_delete_error(response, 403, "The server isn't allowed to delete this")
         }
+        Err(e) => respond_500(
+            response,
+            config,
+            &format!("Failed to delete {}: {}", path.display(), e),
+        ),
     }
-    body.push_str("</ul>");
+}
 
-    let end_html = r#"
-    </body>
-    </html>"#
-        .to_string();
+/// Whether anything under the directory `dir` is left out of listings, such
+/// as dotfiles or credentials. Clients can't see those, so they mustn't be
+/// able to delete them along with it. Entries that can't be read count too.
+fn holds_hidden(dir: &Path, mount: &Mount, config: &Config) -> bool {
+    WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .any(|entry| entry.map_or(true, |entry| listing::is_hidden(&entry, mount, config)))
+}
 
-    let response_body = format!("{}{}{}", begin_html, body, end_html);
-    let response = format!(
-        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
-        response_body.len(),
-        response_body
+fn respond_delete_error(response: &mut Response, status: u16, message: &str) {
+    let headers = format!(
+        "Content-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n",
+        message.len()
     );
+    response.write_head(status, &headers).unwrap_or(());
+    response.write_all(message.as_bytes()).unwrap_or(());
+    response.flush().unwrap_or(());
+}
+
+/// Dumps the request line and headers, for `--verbose`.
+fn log_request_head(client: &str, request: &Request) {
+    let mut dump = format!("{} sent {} {}", client, request.method, request.target);
+    for (name, value) in &request.headers {
+        // Credentials don't belong in logs
+        let value = if name.eq_ignore_ascii_case("Authorization") {
+            "[redacted]"
+        } else {
+            value
+        };
+        dump.push_str(&format!("\n    {}: {}", name, value));
+    }
+    logger::debug(&dump);
+}
+
+/// A short random hex string, used for request IDs and multipart
+/// boundaries.
+fn random_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{:016x}", hasher.finish())
+}
+
+fn log_access(client: &str, request: Option<&Request>, response: &Response, started: Instant) {
+    metrics::record(response.status(), response.bytes_sent());
+    logger::access(&AccessEntry {
+        client,
+        method: request.map_or("-", |request| request.method.as_str()),
+        path: request.map_or("-", |request| request.target.as_str()),
+        status: response.status(),
+        bytes_sent: response.bytes_sent(),
+        elapsed: started.elapsed(),
+        request_id: response.request_id().unwrap_or("-"),
+    });
+}
+
+/// Finds the index page to serve in place of a directory listing, if enabled.
+fn index_file(dir: &Path, mount: &Mount, config: &Config) -> Option<PathBuf> {
+    if !config.serve_index {
+        return None;
+    }
+    // The index is looked up here rather than through `parse_request`, so
+    // it needs the same symlink checks
+    config
+        .index_files
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|candidate| candidate.is_file())
+        .filter(|index| mount.allows_link(index, config.follow_symlinks))
+}
+
+/// Why a request path couldn't be mapped onto the filesystem.
+enum PathError {
+    /// The path has a broken `%` escape or isn't UTF-8 once decoded.
+    Malformed,
+    /// The path resolves outside the root, whether through `..` segments or
+    /// symlinks.
+    OutsideRoot,
+    /// The path goes through a symlink, and `--follow-symlinks` is off.
+    Symlink,
+}
 
-    stream.write_all(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+/// Maps a request path onto the filesystem under `root_dir`, which must be
+/// canonical. Symlinks below
+/// the root are refused unless `follow_symlinks` is set, and even then must
+/// lead somewhere inside it.
+fn parse_request(path: &str, root_dir: &Path, follow_symlinks: bool) -> Result<PathBuf, PathError> {
+    // Segments are decoded one at a time, so an encoded `%2F` can't turn
+    // into a separator and lead into a directory the raw path doesn't name
+    let decoded_path = request::decode_segments(path)
+        .ok_or(PathError::Malformed)?
+        .join("/");
+
+    let resource = root_dir.join(decoded_path.trim_start_matches('/'));
+
+    // Check every step from the root down, since a link to a directory
+    // would expose everything under it
+    if !follow_symlinks {
+        let mut current = root_dir.to_path_buf();
+        for segment in decoded_path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+        {
+            current.push(segment);
+            if fs::symlink_metadata(&current).is_ok_and(|metadata| metadata.is_symlink()) {
+                return Err(PathError::Symlink);
+            }
+        }
+    }
+
+    // `starts_with` compares components lexically, so `root/../etc` would pass
+    // it. Resolve the path first and check its real location instead; the
+    // root was resolved at startup.
+    match resource.canonicalize() {
+        Ok(real_resource) if !real_resource.starts_with(root_dir) => Err(PathError::OutsideRoot),
+        // Paths that don't exist can't leak anything; they'll 404 later
+        _ => Ok(resource),
+    }
 }
 
-fn serve_file(path: &Path, stream: &mut TcpStream) {
+/// Serves a path inside an archive mount: a listing for directories, the
+/// decompressed contents for files.
+fn serve_bundle(
+    bundle: &Bundle,
+    rest: &str,
+    mount: &Mount,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+) {
+    let Some(name) =
+        request::decode_segments(rest).and_then(|segments| bundle::normalize(&segments.join("/")))
+    else {
+        logger::debug(&format!("{} isn't a valid URL path", request.path()));
+        respond_400(response);
+        return;
+    };
+    let node = match bundle.get(&name) {
+        Some(node) if !bundle::is_hidden(&name, node.is_dir, config) => node,
+        Some(node) if config.blocklist.matches(Path::new(&name), node.is_dir) => {
+            respond_403(
+                response,
+                config,
+                "The file looks like it holds credentials, so it isn't served.",
+            );
+            return;
+        }
+        _ => {
+            respond_404(response, request, config);
+            return;
+        }
+    };
+    if !config.within_depth(Path::new(&name), node.is_dir) {
+        respond_403(response, config, TOO_DEEP);
+        return;
+    }
+
+    if node.is_dir && !request.path().ends_with('/') {
+        respond_301(response, &directory_url(request));
+    } else if node.is_dir {
+        listing::serve_bundle_directory(bundle, &name, mount, request, config, response);
+    } else if let Err(e) = serve_bundle_file(bundle, node, &name, request, config, response) {
+        respond_500(
+            response,
+            config,
+            &format!("Failed to read {} in {}: {}", name, mount.root.display(), e),
+        );
+    }
+}
+
+/// Sends a file from inside an archive. Errors before anything is sent are
+/// returned so they can be answered with a 500.
+fn serve_bundle_file(
+    bundle: &Bundle,
+    node: &bundle::Node,
+    name: &str,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+) -> io::Result<()> {
+    let path = Path::new(name);
+    let mut validators = String::new();
+    let mut current_etag = None;
+    // Timestamps an HTTP date can't show are left out, validators and all
+    let last_modified = node
+        .modified
+        .and_then(|modified| Some((modified, response::http_date(modified)?)));
+    if let Some((modified, last_modified)) = last_modified {
+        let etag = entity_tag(node.size, modified);
+        validators = format!(
+            "Last-Modified: {}\r\nETag: {}\r\n{}",
+            last_modified,
+            etag,
+            freshness(path, false, config)
+        );
+        if request.is_unchanged(modified, Some(&etag)) {
+            respond_304(response, &validators);
+            return Ok(());
+        }
+        current_etag = Some(etag);
+    }
+
+    // Entries can only be read from the start, so one range is skipped to
+    // and several get the whole entry instead
+    let range = request
+        .header("Range")
+        .filter(|_| if_range_matches(request, node.modified, current_etag.as_deref()));
+    let (status, content_range, start, length) = match range::parse(range, node.size) {
+        ByteRange::Partial { start, end } => (
+            206,
+            format!("Content-Range: bytes {}-{}/{}\r\n", start, end, node.size),
+            start,
+            end - start + 1,
+        ),
+        ByteRange::Unsatisfiable => {
+            respond_416(response, node.size);
+            return Ok(());
+        }
+        ByteRange::Full | ByteRange::Multiple(_) => (200, String::new(), 0, node.size),
+    };
+
+    bundle.read(node, |contents| {
+        let mut sniffed = Vec::new();
+        contents.take(CHUNK_SIZE as u64).read_to_end(&mut sniffed)?;
+        let content_type = mime::for_file(path, &sniffed);
+
+        let disposition = if request.query_param("download").as_deref() == Some("1")
+            || mime::is_download(&content_type)
+        {
+            "attachment"
+        } else {
+            "inline"
+        };
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        let headers = format!(
+            "Content-Type: {}\r\n{}{}Accept-Ranges: bytes\r\n{}Content-Length: {}\r\n",
+            content_type,
+            response::content_disposition(disposition, &filename),
+            validators,
+            content_range,
+            length
+        );
+        // HEAD gets the same status and range headers, so downloaders can
+        // check a download can be resumed before starting it
+        if response.write_head(status, &headers).is_err() || request.is_head() {
+            return Ok(());
+        }
+
+        // The length was promised up front, so a short or broken entry can
+        // only be signalled by closing the connection
+        if copy_entry_range(contents, &sniffed, response, start, length).is_err() {
+            response.disable_keep_alive();
+        }
+        response.flush().unwrap_or(());
+        Ok(())
+    })
+}
+
+/// Sends `length` bytes from `start` of an archive entry, of which `sniffed`
+/// is the part already read.
+fn copy_entry_range(
+    contents: &mut dyn Read,
+    sniffed: &[u8],
+    response: &mut Response,
+    start: u64,
+    length: u64,
+) -> io::Result<()> {
+    let sniffed_len = sniffed.len() as u64;
+    let from = start.min(sniffed_len) as usize;
+    let to = (start + length).min(sniffed_len) as usize;
+    response.write_all(&sniffed[from..to])?;
+    io::copy(
+        &mut contents.take(start.saturating_sub(sniffed_len)),
+        &mut io::sink(),
+    )?;
+    let rest = length - (to - from) as u64;
+    if io::copy(&mut contents.take(rest), response)? < rest {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn serve_file(
+    path: &Path,
+    mount: &Mount,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+) {
+    // Opening a FIFO blocks until something writes to it, and reading a
+    // device may never end, so only regular files are opened at all
+    if fs::metadata(path).is_ok_and(|metadata| !metadata.is_file()) {
+        respond_403(response, config, NOT_REGULAR);
+        return;
+    }
     let mut file = match File::open(path) {
         Ok(file) => file,
-        Err(_) => {
-            respond_404(stream);
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            respond_404(response, request, config);
+            return;
+        }
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            respond_403(
+                response,
+                config,
+                "The server isn't allowed to read this file.",
+            );
+            return;
+        }
+        Err(e) => {
+            respond_500(
+                response,
+                config,
+                &format!("Failed to open {}: {}", path.display(), e),
+            );
             return;
         }
     };
 
-    let mut content = Vec::new();
-    if file.read_to_end(&mut content).is_err() {
-        respond_500(stream);
+    let metadata = match file.metadata() {
+        // The path may have been replaced since it was checked
+        Ok(metadata) if !metadata.is_file() => {
+            respond_403(response, config, NOT_REGULAR);
+            return;
+        }
+        Ok(metadata) => metadata,
+        Err(e) => {
+            respond_500(
+                response,
+                config,
+                &format!("Failed to inspect {}: {}", path.display(), e),
+            );
+            return;
+        }
+    };
+    let size = metadata.len();
+
+    // Browsers get Markdown and source code as rendered pages
+    let render_markdown = markdown::should_render(path, request, size);
+    let render_source = !render_markdown && highlight::should_render(path, request, size);
+    let rendered = render_markdown || render_source;
+    let thumbnail_width = thumbnail::requested_width(path, request);
+    // Media players and download managers check this before asking for a
+    // range. Generated pages and thumbnails are made whole each time, so
+    // only the file itself can be fetched in pieces.
+    let accept_ranges = if rendered || thumbnail_width.is_some() {
+        "Accept-Ranges: none\r\n"
+    } else {
+        "Accept-Ranges: bytes\r\n"
+    };
+
+    // Let the client reuse its cached copy if the file hasn't changed since
+    let modified = metadata.modified().ok();
+    let mut validators = String::new();
+    let mut current_etag = None;
+    // Timestamps an HTTP date can't show are left out, validators and all
+    let last_modified =
+        modified.and_then(|modified| Some((modified, response::http_date(modified)?)));
+    if let Some((modified, last_modified)) = last_modified {
+        let mut etag = entity_tag(size, modified);
+        if rendered {
+            // The rendered page is a different representation of the file
+            etag.insert_str(etag.len() - 1, "-html");
+        }
+        if let Some(width) = thumbnail_width {
+            etag.insert_str(etag.len() - 1, &format!("-thumb{}", width));
+        }
+        validators = format!(
+            "Last-Modified: {}\r\nETag: {}\r\n{}",
+            last_modified,
+            etag,
+            freshness(path, rendered, config)
+        );
+        if request.is_unchanged(modified, Some(&etag)) {
+            respond_304(response, &validators);
+            return;
+        }
+        current_etag = Some(etag);
+    }
+
+    if let Some(width) = thumbnail_width {
+        let (thumbnail, content_type) = match thumbnail::get(path, modified, width) {
+            Ok(thumbnail) => thumbnail,
+            Err(e) => {
+                respond_500(
+                    response,
+                    config,
+                    &format!("Failed to make a thumbnail of {}: {}", path.display(), e),
+                );
+                return;
+            }
+        };
+        let headers = format!(
+            "Content-Type: {}\r\n{}{}Content-Length: {}\r\n",
+            content_type,
+            validators,
+            accept_ranges,
+            thumbnail.len()
+        );
+        if response.write_head(200, &headers).is_err() {
+            return;
+        }
+        if !request.is_head() {
+            response.write_all(&thumbnail).unwrap_or(());
+        }
+        response.flush().unwrap_or(());
         return;
     }
 
-    // Try to infer the MIME type using the `infer` crate
-    let mime_type = infer::get(&content)
-        .map(|t| t.mime_type().to_string())
-        .unwrap_or_else(|| "text/plain".to_string()); // Default to text/plain if unable to infer
+    // Small files are kept in memory rather than re-read on every request
+    let mut file = match (&config.cache, modified) {
+        (Some(cache), Some(modified)) if size <= cache::MAX_ENTRY_SIZE => {
+            match cache.get(path, modified, size) {
+                Some(bytes) => Contents::Cached(Cursor::new(bytes)),
+                None => {
+                    let mut bytes = Vec::new();
+                    if let Err(e) = file.read_to_end(&mut bytes) {
+                        respond_500(
+                            response,
+                            config,
+                            &format!("Failed to read {}: {}", path.display(), e),
+                        );
+                        return;
+                    }
+                    // Don't cache a file caught in the middle of changing
+                    let bytes = if bytes.len() as u64 == size {
+                        cache.insert(path, modified, bytes)
+                    } else {
+                        bytes.into()
+                    };
+                    Contents::Cached(Cursor::new(bytes))
+                }
+            }
+        }
+        _ => Contents::File(file),
+    };
 
-    // Automatically set to text/plain for unrecognized file extensions
-    let is_text = mime_type.starts_with("text/")
-        || mime_type == "application/json"
-        || mime_type == "image/jpeg"
-        || mime_type == "image/png"
-        || mime_type == "image/gif"
-        || mime_type == "application/pdf"
-        || path.extension().and_then(|ext| ext.to_str()) == Some("rs")  // Rust files
-        || path.extension().and_then(|ext| ext.to_str()) == Some("toml") // TOML files
-        || path.extension().and_then(|ext| ext.to_str()) == Some("lock") // Lock files
-        || mime_type == "text/plain"; // Default fallback for unknown types
+    if rendered {
+        let mut source = Vec::new();
+        if let Err(e) = file.read_to_end(&mut source) {
+            respond_500(
+                response,
+                config,
+                &format!("Failed to read {}: {}", path.display(), e),
+            );
+            return;
+        }
+        let title = path.file_name().unwrap_or_default().to_string_lossy();
+        let source = String::from_utf8_lossy(&source);
+        let page = if render_markdown {
+            markdown::render(&title, &source)
+        } else {
+            highlight::render(path, &title, &source)
+        };
+        html::send(
+            response,
+            request,
+            "text/html; charset=utf-8",
+            &format!("{}{}", validators, accept_ranges),
+            page,
+        );
+        return;
+    }
 
-    // Set Content-Type for Rust, TOML, and lock files as plain text
-    let custom_mime_type = if path.extension().and_then(|ext| ext.to_str()) == Some("rs")
-        || path.extension().and_then(|ext| ext.to_str()) == Some("toml")
-        || path.extension().and_then(|ext| ext.to_str()) == Some("lock")
+    // Only the first chunk is needed to sniff the file type
+    let mut sniffed = Vec::new();
+    if let Err(e) = (&mut file)
+        .take(CHUNK_SIZE as u64)
+        .read_to_end(&mut sniffed)
     {
-        "text/plain"
+        respond_500(
+            response,
+            config,
+            &format!("Failed to read {}: {}", path.display(), e),
+        );
+        return;
+    }
+
+    let content_type = mime::for_file(path, &sniffed);
+
+    // Narrow the body down to the requested byte range, if any
+    // A range of a file that has changed since the client's first part would
+    // corrupt the download, so it gets the whole file again instead
+    let range = request
+        .header("Range")
+        .filter(|_| if_range_matches(request, modified, current_etag.as_deref()));
+    // Several ranges are sent as parts of one body, once the headers are known
+    let mut parts = Vec::new();
+    let (status, content_range, start, length) = match range::parse(range, size) {
+        ByteRange::Full => (200, String::new(), 0, size),
+        ByteRange::Multiple(ranges) => {
+            parts = ranges;
+            (206, String::new(), 0, 0)
+        }
+        ByteRange::Partial { start, end } => (
+            206,
+            format!("Content-Range: bytes {}-{}/{}\r\n", start, end, size),
+            start,
+            end - start + 1,
+        ),
+        ByteRange::Unsatisfiable => {
+            respond_416(response, size);
+            return;
+        }
+    };
+
+    // Browsers show files inline where they can, unless `?download=1` asks
+    // to save them
+    let disposition = if request.query_param("download").as_deref() == Some("1")
+        || mime::is_download(&content_type)
+    {
+        "attachment"
     } else {
-        &mime_type
+        "inline"
     };
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let content_disposition = response::content_disposition(disposition, &filename);
 
-    // Send the appropriate headers and content
-    let response_header = if is_text {
-        // For text, images, PDFs, Rust, TOML, and lock files, display them directly in the browser
-        format!(
-            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
-            custom_mime_type,
-            content.len()
-        )
+    let vary = if markdown::is_markdown(path) || highlight::is_source(path) {
+        "Vary: Accept, Accept-Encoding\r\n"
+    } else if compress::is_compressible(&content_type) {
+        "Vary: Accept-Encoding\r\n"
     } else {
-        // For other file types (e.g., binary files), prompt the download
-        format!(
-            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
-            mime_type,
-            content.len()
-        )
+        ""
     };
 
+    if !parts.is_empty() {
+        serve_multipart(
+            &mut file,
+            &parts,
+            size,
+            &content_type,
+            request,
+            response,
+            &format!(
+                "{}{}{}{}",
+                content_disposition, validators, vary, accept_ranges
+            ),
+        );
+        return;
+    }
+
+    // Build steps often leave compressed copies next to assets, which saves
+    // compressing them on every request. A compressed body is a different
+    // representation, so it's never what a range is taken from.
+    if status == 200 {
+        let usable = |sidecar: &Path| {
+            mount.allows_link(sidecar, config.follow_symlinks)
+                && !config.is_blocked(sidecar, mount, false)
+                && !mount.is_ignored(sidecar, false)
+        };
+        if let Some((sidecar, coding)) = compress::precompressed(path, request, usable) {
+            let vary = if vary.is_empty() {
+                "Vary: Accept-Encoding\r\n"
+            } else {
+                vary
+            };
+            serve_precompressed(
+                &sidecar,
+                coding,
+                request,
+                config,
+                response,
+                &format!(
+                    "Content-Type: {}\r\n{}{}{}{}",
+                    content_type, content_disposition, validators, vary, accept_ranges
+                ),
+            );
+            return;
+        }
+    }
+
+    // Compress text-like files for clients that accept it
+    if let Some(coding) = body_coding(request, status, &content_type, size) {
+        let compressed = match file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| compress::compress(&mut file, coding))
+        {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                respond_500(
+                    response,
+                    config,
+                    &format!("Failed to compress {}: {}", path.display(), e),
+                );
+                return;
+            }
+        };
+        let headers = format!(
+            "Content-Type: {}\r\n{}{}Content-Encoding: {}\r\n{}{}Content-Length: {}\r\n",
+            content_type,
+            content_disposition,
+            validators,
+            coding,
+            vary,
+            accept_ranges,
+            compressed.len()
+        );
+        if response.write_head(status, &headers).is_err() {
+            return;
+        }
+        if !request.is_head() && response.write_all(&compressed).is_err() {
+            return;
+        }
+        response.flush().unwrap_or(());
+        return;
+    }
+
+    let headers = format!(
+        "Content-Type: {}\r\n{}{}{}{}{}Content-Length: {}\r\n",
+        content_type, content_disposition, validators, vary, accept_ranges, content_range, length
+    );
+
     // Write the header and content to the stream
-    if stream.write_all(response_header.as_bytes()).is_err() {
+    if response.write_head(status, &headers).is_err() {
         return; // Unable to send response header
     }
 
-    if stream.write_all(&content).is_err() {
-        return; // Unable to send file content
+    // HEAD gets the same headers as GET, just without the body
+    if request.is_head() {
+        response.flush().unwrap_or(());
+        return;
+    }
+
+    // Content-Length came from the metadata, so a file that shrinks
+    // mid-transfer leaves the body short. The client can't tell where the
+    // next response would start, so the connection has to close.
+    if let Err(e) = copy_range(&mut file, response, start, length) {
+        response.disable_keep_alive();
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            logger::error(&format!(
+                "{} shrank while it was being sent",
+                path.display()
+            ));
+        }
+        return;
+    }
+
+    if let Contents::File(file) = &file {
+        let after = file.metadata();
+        if after.is_ok_and(|after| after.len() != size || after.modified().ok() != modified) {
+            logger::error(&format!(
+                "{} changed while it was being sent",
+                path.display()
+            ));
+        }
+    }
+    response.flush().unwrap_or(());
+}
+
+/// Sends several byte ranges of a file as a `multipart/byteranges` body,
+/// each part with its own `Content-Range`. `headers` describe the file.
+fn serve_multipart<R: Read + Seek>(
+    file: &mut R,
+    ranges: &[(u64, u64)],
+    size: u64,
+    content_type: &str,
+    request: &Request,
+    response: &mut Response,
+    headers: &str,
+) {
+    let boundary = random_id();
+    let part_heads: Vec<String> = ranges
+        .iter()
+        .map(|(start, end)| {
+            format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, content_type, start, end, size
+            )
+        })
+        .collect();
+    let closing = format!("\r\n--{}--\r\n", boundary);
+    let length = part_heads.iter().map(|head| head.len() as u64).sum::<u64>()
+        + ranges
+            .iter()
+            .map(|(start, end)| end - start + 1)
+            .sum::<u64>()
+        + closing.len() as u64;
+
+    let headers = format!(
+        "Content-Type: multipart/byteranges; boundary={}\r\n{}Content-Length: {}\r\n",
+        boundary, headers, length
+    );
+    if response.write_head(206, &headers).is_err() || request.is_head() {
+        response.flush().unwrap_or(());
+        return;
     }
 
-    stream.flush().unwrap_or(());
+    for (head, (start, end)) in part_heads.iter().zip(ranges) {
+        if response.write_all(head.as_bytes()).is_err()
+            || copy_range(file, response, *start, end - start + 1).is_err()
+        {
+            response.disable_keep_alive();
+            return;
+        }
+    }
+    response.write_all(closing.as_bytes()).unwrap_or(());
+    response.flush().unwrap_or(());
 }
 
-fn respond_404(stream: &mut TcpStream) {
-    let response = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+/// The coding to compress a response body in, if any. Range requests are
+/// served from the uncompressed bytes so offsets stay meaningful: only `200`
+/// responses are compressed, never a `206`, not even one for `bytes=0-`
+/// that happens to cover the whole file.
+fn body_coding(
+    request: &Request,
+    status: u16,
+    content_type: &str,
+    size: u64,
+) -> Option<&'static str> {
+    if status != 200 {
+        return None;
+    }
+    compress::choose(request, content_type, size)
 }
 
-fn respond_500(stream: &mut TcpStream) {
-    let response = "HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\nUnable to read file";
-    stream.write_all(response.as_bytes()).unwrap_or(());
+/// Sends the pre-compressed copy of a file at `sidecar`, encoded with
+/// `coding`. `headers` describe the original file.
+fn serve_precompressed(
+    sidecar: &Path,
+    coding: &str,
+    request: &Request,
+    config: &Config,
+    response: &mut Response,
+    headers: &str,
+) {
+    let (mut file, length) = match File::open(sidecar)
+        .and_then(|file| file.metadata().map(|metadata| (file, metadata.len())))
+    {
+        Ok(opened) => opened,
+        Err(e) => {
+            respond_500(
+                response,
+                config,
+                &format!("Failed to open {}: {}", sidecar.display(), e),
+            );
+            return;
+        }
+    };
+
+    let headers = format!(
+        "{}Content-Encoding: {}\r\nContent-Length: {}\r\n",
+        headers, coding, length
+    );
+    if response.write_head(200, &headers).is_err() {
+        return;
+    }
+    if !request.is_head() && copy_range(&mut file, response, 0, length).is_err() {
+        response.disable_keep_alive();
+        return;
+    }
+    response.flush().unwrap_or(());
+}
+
+/// Builds a weak ETag from the file size and modification time, which is
+/// cheap to compute and changes whenever the contents are rewritten.
+fn entity_tag(size: u64, modified: SystemTime) -> String {
+    let nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    format!("W/\"{:x}-{:x}\"", size, nanos)
+}
+
+/// Whether the `If-Range` validator, if any, still describes the file.
+/// Without one, a range request is unconditional. Our ETags are weak only
+/// to cover gzipped copies; they're exactly as precise as the size and
+/// modification time, so they're compared ignoring the `W/`.
+fn if_range_matches(request: &Request, modified: Option<SystemTime>, etag: Option<&str>) -> bool {
+    let Some(validator) = request.header("If-Range") else {
+        return true;
+    };
+    if validator.starts_with('"') || validator.starts_with("W/") {
+        let opaque = |tag: &str| tag.trim_start_matches("W/").to_string();
+        return etag.is_some_and(|etag| opaque(etag) == opaque(validator));
+    }
+    match (httpdate::parse_http_date(validator), modified) {
+        (Ok(date), Some(modified)) => {
+            let modified = response::http_date(modified);
+            modified.is_some() && response::http_date(date) == modified
+        }
+        _ => false,
+    }
+}
+
+/// How long static assets may be cached compared to `--max-age`.
+const ASSET_MAX_AGE_FACTOR: u32 = 24;
+
+/// Builds the `Cache-Control` and `Expires` headers for a file, picking its
+/// lifetime by extension. HTML is revalidated on every use so edits show up
+/// right away, while assets that rarely change are kept for longer.
+fn freshness(path: &Path, rendered: bool, config: &Config) -> String {
+    let content_type = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(mime::from_extension)
+        .unwrap_or("");
+    if rendered || content_type.starts_with("text/html") {
+        return "Cache-Control: no-cache\r\n".to_string();
+    }
+
+    let is_asset = content_type.starts_with("image/")
+        || ["text/css", "text/javascript", "application/wasm"].contains(&content_type);
+    let max_age = if is_asset {
+        config
+            .max_age
+            .checked_mul(ASSET_MAX_AGE_FACTOR)
+            .unwrap_or(Duration::MAX)
+    } else {
+        config.max_age
+    };
+    let expires = SystemTime::now()
+        .checked_add(max_age)
+        .and_then(response::http_date)
+        .unwrap_or_else(|| response::LAST_HTTP_DATE.to_string());
+    format!(
+        "Cache-Control: public, max-age={}\r\nExpires: {}\r\n",
+        max_age.as_secs(),
+        expires
+    )
+}
+
+/// Copies `length` bytes starting at `start` from `file` to `stream`, one
+/// chunk at a time so memory use stays flat regardless of the file size.
+fn copy_range<R: Read + Seek, W: Write>(
+    file: &mut R,
+    stream: &mut W,
+    start: u64,
+    length: u64,
+) -> io::Result<()> {
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut remaining = length;
+    while remaining > 0 {
+        let wanted = remaining.min(CHUNK_SIZE as u64) as usize;
+        let read = match file.read(&mut buffer[..wanted]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        stream.write_all(&buffer[..read])?;
+        remaining -= read as u64;
+    }
+
+    Ok(())
+}
+
+/// The methods the server accepts anywhere, for `Allow` headers.
+fn allowed_methods(config: &Config) -> &'static str {
+    if config.writable {
+        "GET, HEAD, POST, DELETE, OPTIONS"
+    } else {
+        "GET, HEAD, OPTIONS"
+    }
+}
+
+/// The methods that would work on the resource `request` is for: `POST` for
+/// directories uploads are accepted into, and `DELETE` for what may be
+/// deleted. `*` stands for the whole server.
+fn resource_methods(request: &Request, config: &Config) -> &'static str {
+    const READ_ONLY: &str = "GET, HEAD, OPTIONS";
+    if request.target == "*" {
+        return allowed_methods(config);
+    }
+    if !config.writable {
+        return READ_ONLY;
+    }
+    let Some((mount, rest)) = config
+        .strip_base_path(request.path())
+        .and_then(|route| config.find_mount(route))
+    else {
+        return READ_ONLY;
+    };
+    if mount.bundle.is_some() {
+        return READ_ONLY;
+    }
+    let Ok(path) = parse_request(rest, &mount.root, config.follow_symlinks) else {
+        return READ_ONLY;
+    };
+
+    // Paths that are refused or treated as missing can't be changed either
+    let is_dir = path.is_dir();
+    let dotfile = request::decode_segments(rest)
+        .unwrap_or_default()
+        .iter()
+        .any(|segment| segment.starts_with('.') && segment != "." && segment != "..");
+    let real_path = path.canonicalize().unwrap_or_else(|_| path.clone());
+    let relative_path = real_path.strip_prefix(&mount.root).unwrap_or(&real_path);
+    if (dotfile && !config.show_hidden)
+        || config.is_blocked(&path, mount, is_dir)
+        || mount.is_ignored(&path, is_dir)
+        || !config.within_depth(relative_path, is_dir)
+    {
+        return READ_ONLY;
+    }
+
+    let upload = is_dir && config.may_change(&path, mount, false);
+    let delete = fs::symlink_metadata(&path).is_ok()
+        && real_path != mount.root
+        && config.may_change(&path, mount, true);
+    match (upload, delete) {
+        (true, true) => "GET, HEAD, POST, DELETE, OPTIONS",
+        (true, false) => "GET, HEAD, POST, OPTIONS",
+        (false, true) => "GET, HEAD, DELETE, OPTIONS",
+        (false, false) => READ_ONLY,
+    }
+}
+
+/// Answers `OPTIONS`, for both `*` and any path, with the methods allowed
+/// there. With `--cors` this doubles as the answer to preflight requests.
+fn respond_options(response: &mut Response, request: &Request, config: &Config) {
+    let methods = resource_methods(request, config);
+    let mut headers = format!("Allow: {}\r\n", methods);
+    if config.cors.is_some() {
+        let allowed_headers = request
+            .header("Access-Control-Request-Headers")
+            .unwrap_or("Authorization, Range");
+        headers.push_str(&format!(
+            "Access-Control-Allow-Methods: {}\r\nAccess-Control-Allow-Headers: {}\r\nAccess-Control-Max-Age: 86400\r\n",
+            methods, allowed_headers
+        ));
+    }
+    // A 204 has no body, so it mustn't carry a `Content-Length` either
+    response.write_head(204, &headers).unwrap_or(());
+}
+
+fn respond_favicon(response: &mut Response) {
+    let headers = format!(
+        "Content-Type: image/x-icon\r\nCache-Control: public, max-age=86400\r\nContent-Length: {}\r\n",
+        FAVICON.len()
+    );
+    if response.write_head(200, &headers).is_ok() {
+        response.write_all(FAVICON).unwrap_or(());
+    }
+    response.flush().unwrap_or(());
+}
+
+fn respond_301(response: &mut Response, location: &str) {
+    let headers = format!("Location: {}\r\nContent-Length: 0\r\n", location);
+    response.write_head(301, &headers).unwrap_or(());
+}
+
+fn respond_304(response: &mut Response, validators: &str) {
+    response.write_head(304, validators).unwrap_or(());
+}
+
+fn respond_400(response: &mut Response) {
+    let body = html::page(
+        "400 Bad Request",
+        "<h1>400 Bad Request</h1><p>The server couldn't understand the request.</p>",
+    );
+    let headers = format!(
+        "Content-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n",
+        body.len()
+    );
+    response.write_head(400, &headers).unwrap_or(());
+    response.write_all(body.as_bytes()).unwrap_or(());
+    response.flush().unwrap_or(());
+}
+
+fn respond_401(response: &mut Response) {
+    response
+        .write_head(
+            401,
+            "WWW-Authenticate: Basic realm=\"files\"\r\nContent-Length: 0\r\n",
+        )
+        .unwrap_or(());
+}
+
+fn respond_403(response: &mut Response, config: &Config, reason: &str) {
+    let body = custom_error_page(config, "403.html").unwrap_or_else(|| {
+        html::page(
+            "403 Forbidden",
+            &format!("<h1>403 Forbidden</h1><p>{}</p>", reason),
+        )
+        .into_bytes()
+    });
+    send_error_page(response, 403, &body);
+}
+
+fn respond_404(response: &mut Response, request: &Request, config: &Config) {
+    // Prefer the user's own page, but don't let a broken one hide the error
+    let body = config
+        .not_found_page
+        .as_ref()
+        .and_then(|page| fs::read(page).ok())
+        .or_else(|| custom_error_page(config, "404.html"))
+        .unwrap_or_else(|| {
+            let path = request::decode_path(request.path())
+                .unwrap_or_else(|| request.path().to_string());
+            html::page(
+                "404 Not Found",
+                &format!(
+                    "<h1>404 Not Found</h1><p>Nothing exists at <code>{}</code>.</p><p><a href=\"{}/\">⬅️ Back to the root directory</a></p>",
+                    html::escape(&path),
+                    html::escape(&config.base_url())
+                ),
+            )
+            .into_bytes()
+        });
+    send_error_page(response, 404, &body);
+}
+
+fn respond_405(response: &mut Response, request: &Request, config: &Config) {
+    let headers = format!(
+        "Allow: {}\r\nContent-Length: 0\r\n",
+        resource_methods(request, config)
+    );
+    response.write_head(405, &headers).unwrap_or(());
+}
+
+fn respond_408(response: &mut Response) {
+    response
+        .write_head(408, "Content-Length: 0\r\n")
+        .unwrap_or(());
+}
+
+fn respond_416(response: &mut Response, size: u64) {
+    let headers = format!("Content-Range: bytes */{}\r\nContent-Length: 0\r\n", size);
+    response.write_head(416, &headers).unwrap_or(());
+}
+
+/// Sends a generic error page. `error` says what actually went wrong, but
+/// it's only logged, since it names paths on the server.
+fn respond_500(response: &mut Response, config: &Config, error: &str) {
+    logger::error(error);
+    let body = custom_error_page(config, "50x.html").unwrap_or_else(|| {
+        html::page(
+            "500 Internal Server Error",
+            "<h1>500 Internal Server Error</h1><p>Something went wrong while handling this request.</p>",
+        )
+        .into_bytes()
+    });
+    send_error_page(response, 500, &body);
+}
+
+/// The page called `name` in the `--error-pages` directory, if it has one.
+fn custom_error_page(config: &Config, name: &str) -> Option<Vec<u8>> {
+    fs::read(config.error_pages.as_ref()?.join(name)).ok()
+}
+
+fn send_error_page(response: &mut Response, status: u16, body: &[u8]) {
+    let headers = format!(
+        "Content-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n",
+        body.len()
+    );
+    response.write_head(status, &headers).unwrap_or(());
+    response.write_all(body).unwrap_or(());
+    response.flush().unwrap_or(());
+}
+
+/// Describes what the server supports, so frontends can adapt without
+/// probing. Directories are named but their locations aren't given away.
+fn respond_meta(response: &mut Response, request: &Request, config: &Config) {
+    let mounts: Vec<_> = config
+        .mounts
+        .iter()
+        .map(|mount| {
+            let name = mount
+                .root
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            json!({ "prefix": if mount.url_prefix.is_empty() { "/" } else { &mount.url_prefix }, "name": name })
+        })
+        .collect();
+    let meta = json!({
+        "server": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "ranges": true,
+        "encodings": compress::ENCODINGS,
+        "precompressed": ["br", "gzip"],
+        "auth": config.auth.is_some(),
+        "writable": config.writable,
+        "overwrite": config.overwrite,
+        "max_upload": config.writable.then_some(config.max_upload),
+        "cors": config.cors.is_some(),
+        "mounts": mounts,
+    });
+    html::send(
+        response,
+        request,
+        "application/json",
+        "Cache-Control: no-cache\r\n",
+        meta.to_string(),
+    );
+}
+
+fn respond_metrics(response: &mut Response, request: &Request) {
+    html::send(
+        response,
+        request,
+        "text/plain; version=0.0.4; charset=utf-8",
+        "Cache-Control: no-store\r\n",
+        metrics::render(ACTIVE_CONNECTIONS.load(Ordering::SeqCst)),
+    );
+}
+
+fn respond_health(response: &mut Response) {
+    let headers = "Content-Type: text/plain\r\nCache-Control: no-store\r\nContent-Length: 2\r\n";
+    response.write_head(200, headers).unwrap_or(());
+    response.write_all(b"ok").unwrap_or(());
+    response.flush().unwrap_or(());
+}
+
+fn respond_413(response: &mut Response) {
+    response
+        .write_head(413, "Content-Length: 0\r\n")
+        .unwrap_or(());
+}
+
+fn respond_503(response: &mut Response) {
+    let message = "The server is busy, try again shortly";
+    let headers = format!(
+        "Content-Type: text/plain; charset=utf-8\r\nRetry-After: {}\r\nContent-Length: {}\r\n",
+        RETRY_AFTER_SECS,
+        message.len()
+    );
+    response.write_head(503, &headers).unwrap_or(());
+    response.write_all(message.as_bytes()).unwrap_or(());
+    response.flush().unwrap_or(());
+}
+
+fn respond_501(response: &mut Response) {
+    let message = "Request bodies sent with Transfer-Encoding aren't supported";
+    let headers = format!(
+        "Content-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n",
+        message.len()
+    );
+    response.write_head(501, &headers).unwrap_or(());
+    response.write_all(message.as_bytes()).unwrap_or(());
+    response.flush().unwrap_or(());
+}
+
+fn respond_431(response: &mut Response) {
+    response
+        .write_head(431, "Content-Length: 0\r\n")
+        .unwrap_or(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// A fresh directory with a `root` holding `a%2Fb.txt` and
+    /// `sub/file.txt`, next to a `secret.txt` outside it. Returns the
+    /// directory, which must be kept alive, and the root.
+    fn test_root(name: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(name);
+        dir.write("root/a%2Fb.txt", "literal");
+        dir.write("root/sub/file.txt", "inside");
+        dir.write("secret.txt", "outside");
+        let root = dir.path().join("root");
+        (dir, root)
+    }
+
+    #[test]
+    fn encoded_slash_is_malformed() {
+        let (_dir, root) = test_root("slash");
+        let result = parse_request("/sub%2Ffile.txt", &root, false);
+        assert!(matches!(result, Err(PathError::Malformed)));
+    }
+
+    #[test]
+    fn double_encoded_slash_names_the_literal_file() {
+        let (_dir, root) = test_root("literal");
+        let path = parse_request("/a%252Fb.txt", &root, false).ok().unwrap();
+        assert_eq!(path, root.join("a%2Fb.txt"));
+        assert_eq!(fs::read_to_string(path).unwrap(), "literal");
+    }
+
+    #[test]
+    fn parent_segments_cannot_leave_the_root() {
+        let (_dir, root) = test_root("parent");
+        for path in [
+            "/../secret.txt",
+            "/%2E%2E/secret.txt",
+            "/sub/../../secret.txt",
+        ] {
+            let result = parse_request(path, &root, false);
+            assert!(matches!(result, Err(PathError::OutsideRoot)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn parent_segments_inside_the_root_are_allowed() {
+        let (_dir, root) = test_root("inside");
+        let path = parse_request("/sub/%2E%2E/sub/file.txt", &root, false)
+            .ok()
+            .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "inside");
+    }
+
+    /// The status `serve_file` picks for `request` on a file of `size` bytes.
+    fn range_status(request: &Request, size: u64) -> u16 {
+        match range::parse(request.header("Range"), size) {
+            ByteRange::Full => 200,
+            ByteRange::Unsatisfiable => 416,
+            ByteRange::Partial { .. } | ByteRange::Multiple(_) => 206,
+        }
+    }
+
+    #[test]
+    fn ranges_are_never_compressed() {
+        let head = "GET /notes.txt HTTP/1.1\r\nRange: bytes=0-\r\nAccept-Encoding: gzip\r\n\r\n";
+        let request = Request::parse(head).unwrap();
+        let status = range_status(&request, 4096);
+        assert_eq!(status, 206);
+        assert_eq!(body_coding(&request, status, "text/plain", 4096), None);
+    }
+
+    #[test]
+    fn whole_files_are_compressed() {
+        let head = "GET /notes.txt HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n";
+        let request = Request::parse(head).unwrap();
+        let status = range_status(&request, 4096);
+        assert_eq!(status, 200);
+        assert_eq!(
+            body_coding(&request, status, "text/plain", 4096),
+            Some("gzip")
+        );
+    }
 }
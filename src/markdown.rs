@@ -0,0 +1,40 @@
+use crate::html;
+use crate::request::Request;
+use pulldown_cmark::{Options, Parser};
+use std::path::Path;
+
+/// Largest Markdown file rendered to HTML; bigger ones are sent as-is.
+const MAX_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Whether `path` looks like a Markdown document.
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"))
+}
+
+/// Whether to send the Markdown file at `path` rendered as HTML: only for
+/// browsers, and never when `?raw=1` asks for the original text.
+pub fn should_render(path: &Path, request: &Request, size: u64) -> bool {
+    is_markdown(path) && size <= MAX_SIZE && html::wants_rendered(request)
+}
+
+/// Renders a Markdown document as a complete page.
+pub fn render(title: &str, source: &str) -> String {
+    let mut body = String::from("<p class=\"raw\"><a href=\"?raw=1\">View raw</a></p>");
+    body.push_str(&to_html(source));
+    html::page(title, &body)
+}
+
+/// Renders Markdown as an `<article>` to embed in a page.
+pub fn to_html(source: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut article = String::from("<article>");
+    pulldown_cmark::html::push_html(&mut article, Parser::new_ext(source, options));
+    article.push_str("</article>");
+    article
+}
@@ -0,0 +1,58 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters shared by every worker, for `/_metrics`.
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+/// Responses by status class, from `1xx` to `5xx`.
+static RESPONSES: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+/// Counts a response with `status` once it's been sent.
+pub fn record(status: u16, bytes_sent: u64) {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+    BYTES_SENT.fetch_add(bytes_sent, Ordering::Relaxed);
+    if let Some(class) = RESPONSES.get((status / 100).wrapping_sub(1) as usize) {
+        class.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The counters in the Prometheus text format, along with the number of
+/// connections open right now.
+pub fn render(active_connections: usize) -> String {
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "# HELP simple_file_server_requests_total Requests answered.\n\
+         # TYPE simple_file_server_requests_total counter\n\
+         simple_file_server_requests_total {}",
+        REQUESTS.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        text,
+        "# HELP simple_file_server_responses_total Responses sent, by status class.\n\
+         # TYPE simple_file_server_responses_total counter"
+    );
+    for (i, count) in RESPONSES.iter().enumerate() {
+        let _ = writeln!(
+            text,
+            "simple_file_server_responses_total{{class=\"{}xx\"}} {}",
+            i + 1,
+            count.load(Ordering::Relaxed)
+        );
+    }
+    let _ = writeln!(
+        text,
+        "# HELP simple_file_server_bytes_sent_total Response body bytes sent.\n\
+         # TYPE simple_file_server_bytes_sent_total counter\n\
+         simple_file_server_bytes_sent_total {}",
+        BYTES_SENT.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        text,
+        "# HELP simple_file_server_active_connections Connections open now.\n\
+         # TYPE simple_file_server_active_connections gauge\n\
+         simple_file_server_active_connections {}",
+        active_connections
+    );
+    text
+}
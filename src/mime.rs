@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Content types by file extension, consulted before sniffing the contents.
+/// Text formats in particular can't be recognised from their bytes.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("rs", "text/plain"),
+    ("toml", "text/plain"),
+    ("lock", "text/plain"),
+    ("yaml", "text/plain"),
+    ("yml", "text/plain"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("ico", "image/x-icon"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    ("mkv", "video/x-matroska"),
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("vtt", "text/vtt"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("zip", "application/zip"),
+    ("tar", "application/x-tar"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("epub", "application/epub+zip"),
+    ("rtf", "application/rtf"),
+    ("doc", "application/msword"),
+    ("xls", "application/vnd.ms-excel"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+];
+
+/// Archives and office documents, which browsers can't show, so they're
+/// sent as downloads straight away.
+const DOWNLOADS: &[&str] = &[
+    "application/zip",
+    "application/x-tar",
+    "application/gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/epub+zip",
+    "application/rtf",
+    "application/msword",
+    "application/vnd.ms-excel",
+    "application/vnd.ms-powerpoint",
+];
+
+/// Types from `--mime-types`, by lowercase extension. They take precedence
+/// over the built-in ones.
+static OVERRIDES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Sets the extra types from `--mime-types`. Only the first call has any
+/// effect.
+pub fn init(overrides: HashMap<String, String>) {
+    let _ = OVERRIDES.set(overrides);
+}
+
+/// Reads a file in the format of Apache's `mime.types`: a content type
+/// followed by its extensions on each line, with `#` starting a comment.
+pub fn parse_types(text: &str) -> HashMap<String, String> {
+    let mut types = HashMap::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(mime_type) = fields.next() else {
+            continue;
+        };
+        for extension in fields {
+            types.insert(
+                extension.trim_start_matches('.').to_ascii_lowercase(),
+                mime_type.to_string(),
+            );
+        }
+    }
+    types
+}
+
+/// Looks up the content type for a file extension, ignoring case.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    if let Some(mime_type) = OVERRIDES
+        .get()
+        .and_then(|overrides| overrides.get(&extension.to_ascii_lowercase()))
+    {
+        return Some(mime_type);
+    }
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Whether files of this type should be saved rather than displayed.
+pub fn is_download(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or("").trim();
+    DOWNLOADS.contains(&essence)
+        || essence.starts_with("application/vnd.openxmlformats-officedocument.")
+        || essence.starts_with("application/vnd.oasis.opendocument.")
+}
+
+/// Picks the content type for a file from its extension, falling back to
+/// sniffing the first bytes of its contents. Text that looks like UTF-8 is
+/// labelled as such, so browsers don't guess a legacy encoding.
+pub fn for_file(path: &Path, sniffed: &[u8]) -> String {
+    let mime_type = detect(path, sniffed);
+    if is_text(mime_type) && is_utf8(sniffed) {
+        format!("{}; charset=utf-8", mime_type)
+    } else {
+        mime_type.to_string()
+    }
+}
+
+fn detect(path: &Path, sniffed: &[u8]) -> &'static str {
+    if let Some(mime_type) = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(from_extension)
+    {
+        return mime_type;
+    }
+
+    // Try to infer the MIME type using the `infer` crate
+    if let Some(kind) = infer::get(sniffed) {
+        return kind.mime_type();
+    }
+
+    // Unknown content without NUL bytes is most likely text
+    if sniffed.contains(&0) {
+        "application/octet-stream"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Whether a MIME type is a text format that a charset applies to.
+fn is_text(mime_type: &str) -> bool {
+    mime_type.starts_with("text/") || mime_type == "application/xml" || mime_type == "image/svg+xml"
+}
+
+/// Whether `sniffed`, the start of a file, is valid UTF-8. A character cut
+/// off at the end of the chunk doesn't count against it.
+fn is_utf8(sniffed: &[u8]) -> bool {
+    match std::str::from_utf8(sniffed) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
@@ -0,0 +1,181 @@
+/// Most ranges answered in one response. Asking for more gets the whole
+/// resource instead, so a header can't make the server do a lot of small
+/// seeks and writes.
+const MAX_RANGES: usize = 32;
+
+/// The outcome of evaluating a `Range` header against a resource.
+pub enum ByteRange {
+    /// No usable range was requested; serve the whole resource.
+    Full,
+    /// Serve the inclusive byte span `start..=end`.
+    Partial { start: u64, end: u64 },
+    /// Serve several inclusive byte spans, in the order they were asked for.
+    Multiple(Vec<(u64, u64)>),
+    /// The range lies entirely outside the resource.
+    Unsatisfiable,
+}
+
+/// Evaluates a `Range` header value for a resource of `size` bytes.
+///
+/// Headers that aren't a `bytes=` range list, or that fail to parse, are
+/// ignored as RFC 7233 allows, so the caller falls back to a full response.
+/// The same goes for lists of overlapping ranges, which would send some
+/// bytes more than once. Ranges entirely outside the resource are dropped,
+/// and only when none are left is the header unsatisfiable.
+pub fn parse(header: Option<&str>, size: u64) -> ByteRange {
+    let specs = match header.and_then(|value| value.trim().strip_prefix("bytes=")) {
+        Some(specs) => specs,
+        None => return ByteRange::Full,
+    };
+
+    let mut ranges = Vec::new();
+    let mut listed = 0;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        listed += 1;
+        match parse_one(spec, size) {
+            Spec::Invalid => return ByteRange::Full,
+            Spec::Unsatisfiable => {}
+            Spec::Range(start, end) => ranges.push((start, end)),
+        }
+    }
+    // `bytes=` with nothing after it isn't a range list at all
+    if listed == 0 || ranges.len() > MAX_RANGES || overlap(&ranges) {
+        return ByteRange::Full;
+    }
+
+    match ranges[..] {
+        [] => ByteRange::Unsatisfiable,
+        [(start, end)] => ByteRange::Partial { start, end },
+        _ => ByteRange::Multiple(ranges),
+    }
+}
+
+/// One range of a `Range` header.
+enum Spec {
+    Invalid,
+    Unsatisfiable,
+    Range(u64, u64),
+}
+
+fn parse_one(spec: &str, size: u64) -> Spec {
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Spec::Invalid,
+    };
+
+    if start.is_empty() {
+        // Suffix range: the last `n` bytes.
+        return match end.parse::<u64>() {
+            Ok(0) => Spec::Unsatisfiable,
+            Ok(_) if size == 0 => Spec::Unsatisfiable,
+            Ok(n) => Spec::Range(size.saturating_sub(n), size - 1),
+            Err(_) => Spec::Invalid,
+        };
+    }
+
+    let start = match start.parse::<u64>() {
+        Ok(start) => start,
+        Err(_) => return Spec::Invalid,
+    };
+    let end = if end.is_empty() {
+        None
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return Spec::Invalid,
+        }
+    };
+
+    if start >= size {
+        return Spec::Unsatisfiable;
+    }
+
+    Spec::Range(start, end.map_or(size - 1, |end| end.min(size - 1)))
+}
+
+/// Whether any two of the inclusive `ranges` share a byte.
+fn overlap(ranges: &[(u64, u64)]) -> bool {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_unstable();
+    sorted.windows(2).any(|pair| pair[1].0 <= pair[0].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(header: &str, size: u64) -> Option<Vec<(u64, u64)>> {
+        match parse(Some(header), size) {
+            ByteRange::Full => None,
+            ByteRange::Partial { start, end } => Some(vec![(start, end)]),
+            ByteRange::Multiple(ranges) => Some(ranges),
+            ByteRange::Unsatisfiable => Some(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(spans("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(spans("bytes=500-", 1000), Some(vec![(500, 999)]));
+        assert_eq!(spans("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(spans(" bytes=990-2000 ", 1000), Some(vec![(990, 999)]));
+        assert_eq!(spans("bytes=-5000", 1000), Some(vec![(0, 999)]));
+    }
+
+    #[test]
+    fn several_ranges_keep_their_order() {
+        assert_eq!(
+            spans("bytes=500-599, 0-99,-10", 1000),
+            Some(vec![(500, 599), (0, 99), (990, 999)])
+        );
+    }
+
+    #[test]
+    fn ranges_outside_the_resource_are_dropped() {
+        assert_eq!(spans("bytes=0-9,2000-3000", 1000), Some(vec![(0, 9)]));
+        assert_eq!(spans("bytes=1000-", 1000), Some(Vec::new()));
+        assert_eq!(spans("bytes=-0", 1000), Some(Vec::new()));
+        assert_eq!(spans("bytes=0-", 0), Some(Vec::new()));
+        assert_eq!(spans("bytes=-10", 0), Some(Vec::new()));
+    }
+
+    #[test]
+    fn unusable_headers_get_the_whole_resource() {
+        assert!(matches!(parse(None, 1000), ByteRange::Full));
+        assert_eq!(spans("items=0-99", 1000), None);
+        assert_eq!(spans("bytes=", 1000), None);
+        assert_eq!(spans("bytes=,", 1000), None);
+        assert_eq!(spans("bytes=abc", 1000), None);
+        assert_eq!(spans("bytes=99-0", 1000), None);
+        assert_eq!(spans("bytes=0-9,x-y", 1000), None);
+        assert_eq!(spans("bytes=18446744073709551616-", 1000), None);
+    }
+
+    #[test]
+    fn overlapping_ranges_get_the_whole_resource() {
+        assert_eq!(spans("bytes=0-99,50-149", 1000), None);
+        assert_eq!(spans("bytes=0-99,99-100", 1000), None);
+        assert_eq!(spans("bytes=0-,-1", 1000), None);
+        assert_eq!(
+            spans("bytes=0-99,100-199", 1000),
+            Some(vec![(0, 99), (100, 199)])
+        );
+    }
+
+    #[test]
+    fn too_many_ranges_get_the_whole_resource() {
+        let list = |count: u64| {
+            let specs: Vec<String> = (0..count).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+            format!("bytes={}", specs.join(","))
+        };
+        assert_eq!(
+            spans(&list(MAX_RANGES as u64), 1000).map(|r| r.len()),
+            Some(MAX_RANGES)
+        );
+        assert_eq!(spans(&list(MAX_RANGES as u64 + 1), 1000), None);
+    }
+}
@@ -0,0 +1,526 @@
+use crate::stream::Stream;
+use std::io::{self, Cursor, Read};
+use std::mem;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Upper bound on the size of the request line plus headers.
+pub const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+/// Longest `X-Request-Id` taken from a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+pub enum ReadError {
+    /// The headers didn't fit within `MAX_HEADER_SIZE`.
+    TooLarge,
+    /// The client closed the connection before finishing the headers.
+    Closed,
+    /// The client took too long to send the headers. `partial` says whether
+    /// any part of them had arrived.
+    TimedOut {
+        partial: bool,
+    },
+    Io(io::Error),
+}
+
+/// Reads from `stream` until the blank line that ends the request headers.
+///
+/// `buffer` carries bytes already read from the connection, such as the start
+/// of a pipelined request. On success the head (request line and headers,
+/// including the terminating blank line) is returned and `buffer` is left
+/// holding whatever was read past it. The head may arrive in any number of
+/// pieces, and lines may end in a bare `\n` as well as `\r\n`.
+///
+/// The client gets `idle_timeout` to start sending a request, and from then
+/// on `timeout` in total to finish the headers, so trickling in one byte at a
+/// time can't hold the connection open indefinitely.
+pub fn read_head(
+    stream: &mut Stream,
+    buffer: &mut Vec<u8>,
+    idle_timeout: Duration,
+    timeout: Duration,
+) -> Result<Vec<u8>, ReadError> {
+    let mut chunk = [0; 1024];
+    let mut search_from = 0;
+    let mut deadline = if buffer.is_empty() {
+        None
+    } else {
+        Some(Instant::now() + timeout)
+    };
+
+    loop {
+        // Line breaks before the request line, like the one some clients
+        // send after a request body, are skipped
+        let blank = buffer
+            .iter()
+            .take_while(|&&b| b == b'\r' || b == b'\n')
+            .count();
+        if blank > 0 {
+            buffer.drain(..blank);
+            search_from = 0;
+        }
+
+        if let Some(end) = find_terminator(&buffer[search_from..]) {
+            let head_end = search_from + end;
+            if head_end > MAX_HEADER_SIZE {
+                return Err(ReadError::TooLarge);
+            }
+            let rest = buffer.split_off(head_end);
+            return Ok(mem::replace(buffer, rest));
+        }
+
+        if buffer.len() > MAX_HEADER_SIZE {
+            return Err(ReadError::TooLarge);
+        }
+
+        // Only rescan the tail of what we already had, in case the terminator
+        // straddles two reads.
+        search_from = buffer.len().saturating_sub(2);
+
+        let wait = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => idle_timeout,
+        };
+        let timed_out = ReadError::TimedOut {
+            partial: !buffer.is_empty(),
+        };
+        if wait.is_zero() {
+            return Err(timed_out);
+        }
+        stream.set_read_timeout(Some(wait)).map_err(ReadError::Io)?;
+
+        let read = match stream.read(&mut chunk) {
+            Ok(0) => return Err(ReadError::Closed),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(timed_out)
+            }
+            Err(e) => return Err(ReadError::Io(e)),
+        };
+        buffer.extend_from_slice(&chunk[..read]);
+        deadline.get_or_insert_with(|| Instant::now() + timeout);
+    }
+}
+
+/// Finds the blank line ending a head, returning where it ends.
+fn find_terminator(bytes: &[u8]) -> Option<usize> {
+    bytes.iter().enumerate().find_map(|(i, &b)| {
+        if b != b'\n' {
+            return None;
+        }
+        match bytes[i + 1..] {
+            [b'\n', ..] => Some(i + 2),
+            [b'\r', b'\n', ..] => Some(i + 3),
+            _ => None,
+        }
+    })
+}
+
+/// The body of a request: whatever followed the head in the read buffer,
+/// then the rest from the connection, up to `Content-Length` bytes.
+pub struct Body {
+    buffered: Cursor<Vec<u8>>,
+    remaining: u64,
+}
+
+impl Body {
+    /// A body for requests that don't have one.
+    pub fn empty() -> Body {
+        Body::new(Vec::new(), 0)
+    }
+
+    /// A body of `length` bytes, starting with `buffered`.
+    pub fn new(buffered: Vec<u8>, length: u64) -> Body {
+        Body {
+            buffered: Cursor::new(buffered),
+            remaining: length,
+        }
+    }
+
+    /// Reads the body, continuing from `stream` once the buffered bytes run
+    /// out.
+    pub fn reader<'a>(&'a mut self, stream: &'a mut Stream) -> BodyReader<'a> {
+        BodyReader { body: self, stream }
+    }
+}
+
+pub struct BodyReader<'a> {
+    body: &'a mut Body,
+    stream: &'a mut Stream,
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.body.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let limit = buf
+            .len()
+            .min(self.body.remaining.min(usize::MAX as u64) as usize);
+
+        let mut read = self.body.buffered.read(&mut buf[..limit])?;
+        if read == 0 {
+            read = self.stream.read(&mut buf[..limit])?;
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed before the end of the request body",
+                ));
+            }
+        }
+        self.body.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Checks an `If-None-Match` list against `etag` using weak comparison.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|candidate| opaque(candidate) == opaque(etag))
+}
+
+/// Decodes the `%XX` escapes in a URL path. Unlike in query strings, `+`
+/// is an ordinary character here. Returns `None` if a `%` isn't followed by
+/// two hex digits or the result isn't valid UTF-8.
+pub fn decode_path(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // Checked by hand, as `from_str_radix` would take `%+1` too
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Decodes a URL path one segment at a time, like `decode_path`. A segment
+/// that decodes to something with a `/` or NUL in it can't be part of a file
+/// name, and taking it as a separator would resolve a different path than
+/// the one requested, so such paths give `None`.
+pub fn decode_segments(path: &str) -> Option<Vec<String>> {
+    path.split('/')
+        .map(|segment| decode_path(segment).filter(|segment| !segment.contains(['/', '\0'])))
+        .collect()
+}
+
+/// If the URL path `path` starts with `prefix`, a decoded path of whole
+/// segments such as `/my docs`, returns the raw rest of it, which is empty
+/// or starts with `/`. Segments are compared decoded, so a prefix matches
+/// however the client escaped it.
+pub fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut rest = path;
+    for segment in prefix.split('/').skip(1) {
+        let after = rest.strip_prefix('/')?;
+        let (raw, tail) = after.split_at(after.find('/').unwrap_or(after.len()));
+        if decode_path(raw)? != segment {
+            return None;
+        }
+        rest = tail;
+    }
+    Some(rest)
+}
+
+/// Percent-encodes a path for use in a link, keeping the `/` separators.
+/// Everything but unreserved characters is escaped, so `decode_path` gives
+/// back exactly the original.
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The parsed request line and headers of an HTTP request.
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    /// The protocol version from the request line, e.g. `HTTP/1.1`.
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Parses the head returned by `read_head`. Returns `None` if the request
+    /// line isn't of the form `METHOD /target HTTP/x.y`.
+    pub fn parse(head: &str) -> Option<Request> {
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?.to_string();
+        let target = request_line.next()?.to_string();
+        let version = request_line.next()?.to_string();
+
+        let is_token = method
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        if !is_token
+            || !(target.starts_with('/') || target == "*")
+            || !version.starts_with("HTTP/")
+            || request_line.next().is_some()
+        {
+            return None;
+        }
+
+        let headers = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        Some(Request {
+            method,
+            target,
+            version,
+            headers,
+        })
+    }
+
+    /// The request target without its query string.
+    pub fn path(&self) -> &str {
+        self.target
+            .split_once('?')
+            .map_or(self.target.as_str(), |(path, _)| path)
+    }
+
+    /// The raw query string, without the leading `?`.
+    pub fn query(&self) -> &str {
+        self.target.split_once('?').map_or("", |(_, query)| query)
+    }
+
+    /// The decoded `name=value` pairs of the query string, in order. As in
+    /// HTML form submissions, `+` stands for a space.
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        let decode = |text: &str| url_escape::decode(&text.replace('+', " ")).into_owned();
+        self.query()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(name), decode(value))
+            })
+            .collect()
+    }
+
+    /// The value of the first query parameter called `name`.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_params()
+            .into_iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Whether the request was made with HTTP/1.0, which lacks persistent
+    /// connections unless the client asks for them.
+    pub fn is_http10(&self) -> bool {
+        self.version == "HTTP/1.0"
+    }
+
+    /// Whether the client wants the connection kept open after this request.
+    /// That's the default from HTTP/1.1 on, while HTTP/1.0 clients have to
+    /// opt in with `Connection: keep-alive`.
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_option = |wanted: &str| {
+            self.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case(wanted))
+            })
+        };
+        if self.is_http10() {
+            has_option("keep-alive")
+        } else {
+            !has_option("close")
+        }
+    }
+
+    /// Whether the request declares a body, which this server never reads.
+    pub fn has_body(&self) -> bool {
+        self.header("Transfer-Encoding").is_some()
+            || self
+                .header("Content-Length")
+                .is_some_and(|length| length.trim() != "0")
+    }
+
+    /// The declared body length, if any.
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("Content-Length")?.trim().parse().ok()
+    }
+
+    /// The original client address a proxy passed on in `X-Forwarded-For`,
+    /// which is the first one listed.
+    pub fn forwarded_for(&self) -> Option<IpAddr> {
+        let first = self.header("X-Forwarded-For")?.split(',').next()?.trim();
+        first.parse().ok()
+    }
+
+    /// The ID a proxy gave the request in `X-Request-Id`. IDs end up in
+    /// the log, so anything long or unprintable is ignored.
+    pub fn id(&self) -> Option<&str> {
+        self.header("X-Request-Id").filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+    }
+
+    /// Whether the conditional headers show the client's cached copy of a
+    /// response last modified at `modified`, tagged `etag` if it has an
+    /// ETag, is still current, so `304 Not Modified` is the answer. This
+    /// holds for HEAD just as for GET, and never for other methods.
+    pub fn is_unchanged(&self, modified: SystemTime, etag: Option<&str>) -> bool {
+        if self.method != "GET" && !self.is_head() {
+            return false;
+        }
+        // If-None-Match takes precedence over If-Modified-Since when both are sent
+        if let Some(if_none_match) = self.header("If-None-Match") {
+            return etag.is_some_and(|etag| etag_matches(if_none_match, etag));
+        }
+        self.not_modified_since(modified)
+    }
+
+    /// Whether `If-Modified-Since` shows the client already has the version
+    /// last modified at `modified`.
+    fn not_modified_since(&self, modified: SystemTime) -> bool {
+        let since = match self
+            .header("If-Modified-Since")
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+        {
+            Some(since) => since,
+            None => return false,
+        };
+
+        // HTTP dates only have second precision, so compare whole seconds
+        let seconds = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0)
+        };
+        seconds(modified) <= seconds(since)
+    }
+
+    /// Whether the response should carry headers only.
+    pub fn is_head(&self) -> bool {
+        self.method == "HEAD"
+    }
+
+    /// Looks up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_paths_decode_to_the_original() {
+        for path in [
+            "/plain.txt",
+            "/with space/and more spaces.txt",
+            "/ünïcödé/日本語.txt",
+            "/emoji 🎉.png",
+            "/a+b=c&d?e#f%g.txt",
+        ] {
+            assert_eq!(decode_path(&encode_path(path)).as_deref(), Some(path));
+        }
+    }
+
+    #[test]
+    fn encoding_escapes_spaces_and_unicode() {
+        assert_eq!(encode_path("/my file.txt"), "/my%20file.txt");
+        assert_eq!(encode_path("/é"), "/%C3%A9");
+        assert_eq!(encode_path("/a+b"), "/a%2Bb");
+    }
+
+    #[test]
+    fn plus_is_kept_literally() {
+        assert_eq!(decode_path("/a+b.txt").as_deref(), Some("/a+b.txt"));
+        assert_eq!(decode_path("/a%2Bb.txt").as_deref(), Some("/a+b.txt"));
+        assert_eq!(decode_path("/a%20b.txt").as_deref(), Some("/a b.txt"));
+    }
+
+    #[test]
+    fn malformed_escapes_give_none() {
+        assert_eq!(decode_path("/100%"), None);
+        assert_eq!(decode_path("/%4"), None);
+        assert_eq!(decode_path("/%zz"), None);
+        assert_eq!(decode_path("/%+1"), None);
+        // Decodes to bytes that aren't valid UTF-8
+        assert_eq!(decode_path("/%FF%FE"), None);
+    }
+
+    #[test]
+    fn encoded_separators_are_rejected_within_a_segment() {
+        assert_eq!(decode_segments("/docs%2Fsecret.txt"), None);
+        assert_eq!(decode_segments("/docs%2fsecret.txt"), None);
+        assert_eq!(decode_segments("/%2E%2E%2Fetc%2Fpasswd"), None);
+        assert_eq!(decode_segments("/file%00.txt"), None);
+    }
+
+    #[test]
+    fn double_encoded_separators_stay_literal() {
+        assert_eq!(
+            decode_segments("/a%252Fb.txt"),
+            Some(vec![String::new(), "a%2Fb.txt".to_string()])
+        );
+        assert_eq!(
+            decode_segments(&encode_path("/a%2Fb.txt")),
+            Some(vec![String::new(), "a%2Fb.txt".to_string()])
+        );
+    }
+
+    #[test]
+    fn encoded_dots_decode_to_their_own_segment() {
+        assert_eq!(
+            decode_segments("/%2E%2E/secret.txt"),
+            Some(vec![
+                String::new(),
+                "..".to_string(),
+                "secret.txt".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn prefixes_match_whole_decoded_segments() {
+        assert_eq!(strip_path_prefix("/docs/a.txt", "/docs"), Some("/a.txt"));
+        assert_eq!(strip_path_prefix("/docs", "/docs"), Some(""));
+        assert_eq!(strip_path_prefix("/docsx/a.txt", "/docs"), None);
+        assert_eq!(strip_path_prefix("/a.txt", ""), Some("/a.txt"));
+        assert_eq!(
+            strip_path_prefix("/my%20docs/a%20b.txt", "/my docs"),
+            Some("/a%20b.txt")
+        );
+        assert_eq!(strip_path_prefix("/a%23b/c", "/a#b"), Some("/c"));
+        assert_eq!(strip_path_prefix("/a/b/c", "/a/b"), Some("/c"));
+        // An encoded slash isn't a separator
+        assert_eq!(strip_path_prefix("/a%2Fb/c", "/a/b"), None);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::logger;
+use crate::stream::Stream;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes a response to the client while keeping track of the status and the
+/// number of body bytes sent, for the access log. A failed write means the
+/// client is gone or the response is cut short, so the connection is closed
+/// afterwards either way and callers are free to ignore the error.
+pub struct Response<'a> {
+    stream: &'a mut Stream,
+    keep_alive: bool,
+    head_only: bool,
+    /// Whether to answer as HTTP/1.0, for clients that spoke it.
+    http10: bool,
+    status: u16,
+    bytes_sent: u64,
+    /// Header lines sent with every response, whatever its status.
+    extra_headers: String,
+    request_id: Option<String>,
+    /// Whether body writes are being framed as chunks, until `finish`.
+    chunked: bool,
+}
+
+impl<'a> Response<'a> {
+    /// `keep_alive` says whether the connection stays open afterwards; when it
+    /// doesn't, the client is told so with `Connection: close`. With
+    /// `head_only` set, as for HEAD requests, body writes are discarded.
+    pub fn new(stream: &'a mut Stream, keep_alive: bool, head_only: bool) -> Response<'a> {
+        Response {
+            stream,
+            keep_alive,
+            head_only,
+            http10: false,
+            status: 0,
+            bytes_sent: 0,
+            extra_headers: String::new(),
+            request_id: None,
+            chunked: false,
+        }
+    }
+
+    /// Tags the response with the ID of the request it answers, echoed back
+    /// in `X-Request-Id` and logged alongside it.
+    pub fn set_request_id(&mut self, id: &str) {
+        self.add_header("X-Request-Id", id);
+        self.request_id = Some(id.to_string());
+    }
+
+    /// Adds a header to be sent along with whatever `write_head` is given.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.extra_headers
+            .push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    /// Answers with an HTTP/1.0 status line. Such clients only keep the
+    /// connection open when told `Connection: keep-alive`.
+    pub fn use_http10(&mut self) {
+        self.http10 = true;
+    }
+
+    /// Writes the status line followed by `headers`, each of which must
+    /// already end in `\r\n`, and the blank line that ends the head.
+    pub fn write_head(&mut self, status: u16, headers: &str) -> io::Result<()> {
+        self.status = status;
+        let (version, connection) = match (self.http10, self.keep_alive) {
+            (false, true) => ("HTTP/1.1", ""),
+            (false, false) => ("HTTP/1.1", "Connection: close\r\n"),
+            (true, true) => ("HTTP/1.0", "Connection: keep-alive\r\n"),
+            (true, false) => ("HTTP/1.0", "Connection: close\r\n"),
+        };
+        let head = format!(
+            "{} {} {}\r\n{}{}{}\r\n",
+            version,
+            status,
+            reason(status),
+            headers,
+            self.extra_headers,
+            connection
+        );
+        let result = self.stream.write_all(head.as_bytes());
+        self.check(result)
+    }
+
+    /// Like `write_head`, for a body whose length isn't known up front. It's
+    /// sent with `Transfer-Encoding: chunked`, so the connection can be kept
+    /// open, and must be ended with `finish`. HTTP/1.0 clients don't know
+    /// about chunks, so they get the bare body and the connection closes.
+    pub fn write_chunked_head(&mut self, status: u16, headers: &str) -> io::Result<()> {
+        if self.http10 {
+            self.keep_alive = false;
+            return self.write_head(status, headers);
+        }
+        self.write_head(
+            status,
+            &format!("{}Transfer-Encoding: chunked\r\n", headers),
+        )?;
+        self.chunked = !self.head_only;
+        Ok(())
+    }
+
+    /// Ends a body started with `write_chunked_head`. A chunked body that's
+    /// never finished leaves the client waiting for more, so the connection
+    /// isn't reused for another response.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.chunked {
+            let result = self.stream.write_all(b"0\r\n\r\n");
+            self.check(result)?;
+            self.chunked = false;
+        }
+        self.flush()
+    }
+
+    /// Closes the connection after this response, for bodies whose end is
+    /// only marked by the connection closing or that were cut short. The
+    /// client is only told with `Connection: close` if this is called before
+    /// `write_head`.
+    pub fn disable_keep_alive(&mut self) {
+        self.keep_alive = false;
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive && !self.chunked
+    }
+
+    /// The connection itself, for reading a request body before responding.
+    pub fn stream(&mut self) -> &mut Stream {
+        self.stream
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Notes a failed write, so the connection isn't reused.
+    fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &result {
+            self.keep_alive = false;
+            if is_disconnect(e) {
+                logger::debug("Client closed the connection mid-response");
+            } else {
+                logger::debug(&format!("Failed to write a response: {}", e));
+            }
+        }
+        result
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+}
+
+/// Body bytes go through `Write` so they're counted.
+impl Write for Response<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.head_only {
+            return Ok(buf.len());
+        }
+        if self.chunked {
+            // An empty chunk would end the body
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            let result = write_chunk(self.stream, buf);
+            self.check(result)?;
+            self.bytes_sent += buf.len() as u64;
+            return Ok(buf.len());
+        }
+        let result = self.stream.write(buf);
+        let written = self.check(result)?;
+        self.bytes_sent += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.stream.flush();
+        self.check(result)
+    }
+}
+
+fn write_chunk(stream: &mut Stream, chunk: &[u8]) -> io::Result<()> {
+    stream.write_all(format!("{:x}\r\n", chunk.len()).as_bytes())?;
+    stream.write_all(chunk)?;
+    stream.write_all(b"\r\n")
+}
+
+/// Whether `error` means the client went away, which is routine rather than
+/// something to report.
+pub fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// A `Content-Disposition` header line for `filename`. Names that aren't
+/// plain ASCII get an RFC 5987 `filename*` alongside a simplified fallback.
+pub fn content_disposition(disposition: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if fallback == filename {
+        return format!(
+            "Content-Disposition: {}; filename=\"{}\"\r\n",
+            disposition, filename
+        );
+    }
+
+    let mut encoded = String::new();
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!(
+        "Content-Disposition: {}; filename=\"{}\"; filename*=UTF-8''{}\r\n",
+        disposition, fallback, encoded
+    )
+}
+
+/// The last second an HTTP date can show, at the end of the year 9999.
+const MAX_HTTP_DATE: u64 = 253_402_300_799;
+
+/// `MAX_HTTP_DATE` written out, for dates that have to be clamped to it.
+pub const LAST_HTTP_DATE: &str = "Fri, 31 Dec 9999 23:59:59 GMT";
+
+/// Formats `time` as an HTTP date. Times before 1970 or after 9999 can't be
+/// written that way, so they give `None`.
+pub fn http_date(time: SystemTime) -> Option<String> {
+    let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (secs <= MAX_HTTP_DATE).then(|| httpdate::fmt_http_date(time))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "NO CONTENT",
+        206 => "PARTIAL CONTENT",
+        301 => "MOVED PERMANENTLY",
+        303 => "SEE OTHER",
+        304 => "NOT MODIFIED",
+        400 => "BAD REQUEST",
+        401 => "UNAUTHORIZED",
+        403 => "FORBIDDEN",
+        404 => "NOT FOUND",
+        405 => "METHOD NOT ALLOWED",
+        408 => "REQUEST TIMEOUT",
+        409 => "CONFLICT",
+        411 => "LENGTH REQUIRED",
+        413 => "PAYLOAD TOO LARGE",
+        415 => "UNSUPPORTED MEDIA TYPE",
+        416 => "RANGE NOT SATISFIABLE",
+        431 => "REQUEST HEADER FIELDS TOO LARGE",
+        500 => "INTERNAL SERVER ERROR",
+        501 => "NOT IMPLEMENTED",
+        503 => "SERVICE UNAVAILABLE",
+        _ => "",
+    }
+}
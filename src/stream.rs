@@ -0,0 +1,116 @@
+use crate::throttle::Throttle;
+use rustls::{ServerConnection, StreamOwned};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Largest read or write made in one go on a throttled connection, so the
+/// pauses between them stay short and the rate stays even.
+const THROTTLED_CHUNK_SIZE: usize = 16 * 1024;
+
+/// A client connection, either plain or wrapped in TLS. Everything above
+/// this reads and writes it the same way.
+pub struct Stream {
+    inner: Inner,
+    /// Paces reads and writes for `--rate-limit`, each direction separately.
+    throttle: Option<(Throttle, Throttle)>,
+}
+
+enum Inner {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Stream {
+    pub fn plain(stream: TcpStream) -> Stream {
+        Stream {
+            inner: Inner::Plain(stream),
+            throttle: None,
+        }
+    }
+
+    pub fn tls(stream: StreamOwned<ServerConnection, TcpStream>) -> Stream {
+        Stream {
+            inner: Inner::Tls(Box::new(stream)),
+            throttle: None,
+        }
+    }
+
+    /// Limits the connection to about `rate` bytes per second each way.
+    pub fn set_rate_limit(&mut self, rate: u64) {
+        self.throttle = Some((Throttle::new(rate), Throttle::new(rate)));
+    }
+
+    /// The most to read or write at once.
+    fn chunk_size(&self) -> usize {
+        match self.throttle {
+            Some(_) => THROTTLED_CHUNK_SIZE,
+            None => usize::MAX,
+        }
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match &self.inner {
+            Inner::Plain(stream) => stream,
+            Inner::Tls(stream) => &stream.sock,
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().peer_addr()
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(timeout)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = buf.len().min(self.chunk_size());
+        let buf = &mut buf[..limit];
+        let read = match &mut self.inner {
+            Inner::Plain(stream) => stream.read(buf)?,
+            Inner::Tls(stream) => stream.read(buf)?,
+        };
+        if let Some((throttle, _)) = &mut self.throttle {
+            throttle.pace(read);
+        }
+        Ok(read)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let buf = &buf[..buf.len().min(self.chunk_size())];
+        let written = match &mut self.inner {
+            Inner::Plain(stream) => stream.write(buf)?,
+            Inner::Tls(stream) => stream.write(buf)?,
+        };
+        if let Some((_, throttle)) = &mut self.throttle {
+            throttle.pace(written);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Inner::Plain(stream) => stream.flush(),
+            Inner::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        // Tell TLS clients the connection ended on purpose, so a body that
+        // runs until the connection closes isn't mistaken for a truncated one
+        if let Inner::Tls(stream) = &mut self.inner {
+            stream.conn.send_close_notify();
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .unwrap_or_default();
+        }
+    }
+}
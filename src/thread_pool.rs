@@ -0,0 +1,98 @@
+use crate::logger;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed-size pool of worker threads that pull jobs off a shared queue.
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+    /// Creates a pool with `size` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|id| Worker::new(id, Arc::clone(&receiver)))
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// Queues `f` to run on the next idle worker.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &self.sender {
+            // Sending only fails once every worker has exited, at which point
+            // there is nobody left to run the job anyway.
+            let _ = sender.send(Box::new(f));
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel makes each worker's `recv` fail once the queue
+        // is drained, which ends its loop.
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    logger::error(&format!("Worker {} exited abnormally", worker.id));
+                }
+            }
+        }
+    }
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        let thread = thread::spawn(move || loop {
+            // Release the lock before running the job so other workers can
+            // pick up the next one.
+            let message = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => break,
+            };
+
+            match message {
+                Ok(job) => {
+                    // A panicking job shouldn't take the worker down with it,
+                    // otherwise the pool would shrink with every bad request.
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        logger::error(&format!("Worker {} recovered from a panicked job", id));
+                    }
+                }
+                Err(_) => break,
+            }
+        });
+
+        Worker {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How far behind the target rate a connection may fall, while idle, before
+/// it stops counting. Without this a connection kept open for a while could
+/// save up enough to send a burst far above the rate.
+const MAX_BURST: Duration = Duration::from_secs(1);
+
+/// Keeps a flow of bytes to roughly a steady rate by sleeping whenever it
+/// gets ahead.
+pub struct Throttle {
+    /// Bytes per second.
+    rate: u64,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    pub fn new(rate: u64) -> Throttle {
+        Throttle {
+            rate: rate.max(1),
+            started: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Counts `bytes` as sent, then waits until sending them would have
+    /// kept to the rate.
+    pub fn pace(&mut self, bytes: usize) {
+        if self.started.elapsed() > self.due() + MAX_BURST {
+            self.started = Instant::now();
+            self.bytes = 0;
+        }
+        self.bytes += bytes as u64;
+        if let Some(wait) = self.due().checked_sub(self.started.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// How long the bytes so far should take at the rate.
+    fn due(&self) -> Duration {
+        Duration::from_secs_f64(self.bytes as f64 / self.rate as f64)
+    }
+}
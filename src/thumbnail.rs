@@ -0,0 +1,165 @@
+use crate::request::Request;
+use image::{ImageFormat, ImageReader, Limits};
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+/// Width used when `?thumb=1` doesn't say, and the bounds a request may ask
+/// for.
+const DEFAULT_WIDTH: u32 = 128;
+const MIN_WIDTH: u32 = 16;
+const MAX_WIDTH: u32 = 512;
+
+/// Images are refused rather than decoded past these limits, which keeps
+/// the memory a single thumbnail can take bounded.
+const MAX_SOURCE_DIMENSION: u32 = 16_384;
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Whether thumbnails can be made for the file at `path`.
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ["jpg", "jpeg", "png", "gif"]
+                .iter()
+                .any(|supported| ext.eq_ignore_ascii_case(supported))
+        })
+}
+
+/// The thumbnail width asked for with `?thumb=1&w=<width>`, if the request
+/// is for a thumbnail of a supported image.
+pub fn requested_width(path: &Path, request: &Request) -> Option<u32> {
+    if !is_supported(path) || request.query_param("thumb").as_deref() != Some("1") {
+        return None;
+    }
+    let width = request
+        .query_param("w")
+        .and_then(|width| width.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH);
+    Some(width.clamp(MIN_WIDTH, MAX_WIDTH))
+}
+
+/// Returns a thumbnail of the image at `path` fitting in `width` pixels
+/// square, along with its content type. Thumbnails are cached on disk, keyed
+/// by the image's path and modification time, so each is only made once.
+/// Without a private cache directory they're made afresh every time.
+pub fn get(
+    path: &Path,
+    modified: Option<SystemTime>,
+    width: u32,
+) -> io::Result<(Vec<u8>, &'static str)> {
+    let (format, content_type) = if ImageFormat::from_path(path).ok() == Some(ImageFormat::Jpeg) {
+        (ImageFormat::Jpeg, "image/jpeg")
+    } else {
+        (ImageFormat::Png, "image/png")
+    };
+
+    let cached = cache_path(path, modified, width, format);
+    if let Some(bytes) = cached.as_ref().and_then(|cached| fs::read(cached).ok()) {
+        return Ok((bytes, content_type));
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = ImageReader::open(path)?.with_guessed_format()?;
+    reader.limits(limits);
+    let image = reader.decode().map_err(io::Error::other)?;
+
+    let mut bytes = Vec::new();
+    image
+        .thumbnail(width, width)
+        .write_to(&mut Cursor::new(&mut bytes), format)
+        .map_err(io::Error::other)?;
+
+    // A failed write only means the thumbnail is made again next time
+    if let Some(cached) = cached {
+        store(&cached, &bytes).unwrap_or(());
+    }
+    Ok((bytes, content_type))
+}
+
+fn cache_path(
+    path: &Path,
+    modified: Option<SystemTime>,
+    width: u32,
+    format: ImageFormat,
+) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    modified.hash(&mut hasher);
+    width.hash(&mut hasher);
+
+    let extension = format.extensions_str().first().copied().unwrap_or("img");
+    Some(cache_dir()?.join(format!("{:016x}.{}", hasher.finish(), extension)))
+}
+
+/// The directory thumbnails are cached in, inside the user's own cache
+/// directory rather than the shared temp directory, where anyone could put
+/// files for the server to send. It's created on first use, readable only by
+/// this user; if it can't be, or someone else owns it, nothing is cached.
+fn cache_dir() -> Option<&'static Path> {
+    static DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let base = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+        let dir = base.join("simple-file-server").join("thumbnails");
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&dir).ok()?;
+        is_private(&dir).then_some(dir)
+    })
+    .as_deref()
+}
+
+/// Whether `dir` is a real directory owned by this user that nobody else
+/// can get into.
+#[cfg(unix)]
+fn is_private(dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // SAFETY: `getuid` has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+    fs::symlink_metadata(dir).is_ok_and(|metadata| {
+        metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o077 == 0
+    })
+}
+
+#[cfg(not(unix))]
+fn is_private(dir: &Path) -> bool {
+    fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Writes the thumbnail under a temporary name first, so concurrent
+/// requests never read a half-written file. The temporary file must be new,
+/// so nothing put there beforehand is written through.
+fn store(cached: &Path, bytes: &[u8]) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let temporary = cached.with_extension(format!(
+        "{}-{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temporary)?;
+    file.write_all(bytes)
+        .and_then(|()| fs::rename(&temporary, cached))
+        .inspect_err(|_| {
+            fs::remove_file(&temporary).unwrap_or(());
+        })
+}
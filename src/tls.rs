@@ -0,0 +1,23 @@
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Builds the TLS configuration from a PEM certificate chain and private key.
+pub fn load(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificate {}: {}", cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", cert.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("Failed to read private key {}: {}", key.display(), e))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+    Ok(Arc::new(config))
+}
@@ -0,0 +1,611 @@
+use crate::config::{Config, Mount};
+use crate::request::Request;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// How much of the body is read at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Upper bound on the headers of a single part.
+const MAX_PART_HEADER_SIZE: usize = 16 * 1024;
+
+pub enum UploadError {
+    /// The body isn't `multipart/form-data`.
+    UnsupportedType,
+    /// The request didn't say how long its body is.
+    LengthRequired,
+    /// The body is larger than `--max-upload`.
+    TooLarge,
+    /// A file with the uploaded name already exists.
+    Exists(String),
+    /// The uploaded name is taken by a symlink, which is never written
+    /// through.
+    Symlink(String),
+    /// The uploaded name is one the server hides or refuses to serve.
+    Hidden(String),
+    /// The body isn't valid multipart data or contains a bad file name.
+    Malformed(&'static str),
+    Io(io::Error),
+}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> UploadError {
+        UploadError::Io(e)
+    }
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UploadError::UnsupportedType => write!(f, "Uploads must be multipart/form-data"),
+            UploadError::LengthRequired => write!(f, "Uploads must have a Content-Length"),
+            UploadError::TooLarge => write!(f, "Upload is larger than the server allows"),
+            UploadError::Exists(name) => write!(f, "{} already exists", name),
+            UploadError::Symlink(name) => write!(f, "{} is a symlink, not replaced", name),
+            UploadError::Hidden(name) => write!(f, "{} is a name the server doesn't serve", name),
+            UploadError::Malformed(reason) => write!(f, "Malformed upload: {}", reason),
+            UploadError::Io(e) => write!(f, "Failed to save upload: {}", e),
+        }
+    }
+}
+
+/// Saves every file in a `multipart/form-data` body into `dir`, a directory
+/// in `mount`, returning how many were written. Files are streamed to disk
+/// as they arrive.
+pub fn save(
+    dir: &Path,
+    mount: &Mount,
+    request: &Request,
+    body: &mut impl Read,
+    config: &Config,
+) -> Result<usize, UploadError> {
+    let boundary = request
+        .header("Content-Type")
+        .and_then(multipart_boundary)
+        .ok_or(UploadError::UnsupportedType)?;
+    let length = request
+        .content_length()
+        .ok_or(UploadError::LengthRequired)?;
+    if length > config.max_upload {
+        return Err(UploadError::TooLarge);
+    }
+
+    let mut parts = Multipart::new(body, &boundary);
+    parts.skip_to_delimiter()?;
+
+    let mut saved = 0;
+    while parts.next_part()? {
+        let headers = parts.read_part_headers()?;
+        let filename = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Disposition"))
+            .and_then(|(_, value)| disposition_filename(value));
+
+        // Other form fields, and file inputs left empty, are ignored
+        let filename = match filename {
+            Some(filename) if !filename.is_empty() => safe_filename(&filename)?,
+            _ => {
+                parts.copy_to_delimiter(&mut io::sink())?;
+                continue;
+            }
+        };
+
+        // Files the server hides or refuses couldn't be fetched or deleted
+        // afterwards, and a new `.serverignore` would change what's hidden
+        let destination = dir.join(&filename);
+        if (filename.starts_with('.') && !config.show_hidden)
+            || mount.is_ignored(&destination, false)
+            || config.is_blocked(&destination, mount, false)
+        {
+            return Err(UploadError::Hidden(filename));
+        }
+
+        // Writing through a symlink could replace a file outside the root
+        if fs::symlink_metadata(&destination).is_ok_and(|metadata| metadata.is_symlink()) {
+            return Err(UploadError::Symlink(filename));
+        }
+        let mut options = OpenOptions::new();
+        options.write(true);
+        if config.overwrite {
+            options.create(true).truncate(true);
+            // Closes the window for a symlink to appear after the check
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+        } else {
+            // Fails if the file exists, without a window for a race
+            options.create_new(true);
+        }
+        let mut file: File = match options.open(&destination) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(UploadError::Exists(filename))
+            }
+            #[cfg(unix)]
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+                return Err(UploadError::Symlink(filename))
+            }
+            Err(e) => return Err(UploadError::Io(e)),
+        };
+
+        // Don't leave half-written files behind
+        if let Err(e) = parts.copy_to_delimiter(&mut file) {
+            drop(file);
+            fs::remove_file(&destination).unwrap_or(());
+            return Err(e);
+        }
+        saved += 1;
+    }
+
+    if saved == 0 {
+        return Err(UploadError::Malformed("no files were sent"));
+    }
+    Ok(saved)
+}
+
+/// Extracts the boundary from a `multipart/form-data` content type.
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Extracts the `filename` parameter of a `Content-Disposition` header.
+fn disposition_filename(value: &str) -> Option<String> {
+    value
+        .split(';')
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("filename"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+/// Reduces an uploaded file name to its last component, so it can only ever
+/// land in the target directory.
+fn safe_filename(filename: &str) -> Result<String, UploadError> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or("").trim();
+    if name.is_empty() || name == "." || name == ".." || name.contains('\0') {
+        return Err(UploadError::Malformed("invalid file name"));
+    }
+    Ok(name.to_string())
+}
+
+/// A streaming reader over the parts of a multipart body.
+struct Multipart<R> {
+    body: R,
+    buffer: Vec<u8>,
+    /// `\r\n--boundary`, which ends every part.
+    delimiter: Vec<u8>,
+}
+
+impl<R: Read> Multipart<R> {
+    fn new(body: R, boundary: &str) -> Multipart<R> {
+        Multipart {
+            body,
+            // The first delimiter isn't preceded by a line break, so pretend
+            // it is to find it like the rest
+            buffer: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+        }
+    }
+
+    /// Reads another chunk of the body, failing if it has ended.
+    fn fill(&mut self) -> Result<(), UploadError> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = self.body.read(&mut chunk)?;
+        if read == 0 {
+            return Err(UploadError::Malformed("body ended early"));
+        }
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(())
+    }
+
+    fn skip_to_delimiter(&mut self) -> Result<(), UploadError> {
+        self.copy_to_delimiter(&mut io::sink())
+    }
+
+    /// Writes everything up to the next delimiter to `sink` and consumes the
+    /// delimiter itself.
+    fn copy_to_delimiter<W: Write>(&mut self, sink: &mut W) -> Result<(), UploadError> {
+        loop {
+            if let Some(pos) = find(&self.buffer, &self.delimiter) {
+                sink.write_all(&self.buffer[..pos])?;
+                self.buffer.drain(..pos + self.delimiter.len());
+                return Ok(());
+            }
+
+            // Hold back enough to catch a delimiter split across reads
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let flushed = self.buffer.len() - keep;
+                sink.write_all(&self.buffer[..flushed])?;
+                self.buffer.drain(..flushed);
+            }
+            self.fill()?;
+        }
+    }
+
+    /// After a delimiter, checks whether another part follows or the body
+    /// is complete.
+    fn next_part(&mut self) -> Result<bool, UploadError> {
+        while self.buffer.len() < 2 {
+            self.fill()?;
+        }
+        if self.buffer.starts_with(b"--") {
+            return Ok(false);
+        }
+
+        // Skip any padding up to the line break that starts the part
+        loop {
+            if let Some(pos) = find(&self.buffer, b"\r\n") {
+                self.buffer.drain(..pos + 2);
+                return Ok(true);
+            }
+            if self.buffer.len() > MAX_PART_HEADER_SIZE {
+                return Err(UploadError::Malformed("bad delimiter"));
+            }
+            self.fill()?;
+        }
+    }
+
+    fn read_part_headers(&mut self) -> Result<Vec<(String, String)>, UploadError> {
+        loop {
+            if self.buffer.starts_with(b"\r\n") {
+                self.buffer.drain(..2);
+                return Ok(Vec::new());
+            }
+            if let Some(pos) = find(&self.buffer, b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&self.buffer[..pos]).into_owned();
+                self.buffer.drain(..pos + 4);
+                return Ok(head
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                    .collect());
+            }
+            if self.buffer.len() > MAX_PART_HEADER_SIZE {
+                return Err(UploadError::Malformed("part headers too large"));
+            }
+            self.fill()?;
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn config(root: &Path, flags: &[&str]) -> Config {
+        let args = [
+            root.to_string_lossy().into_owned(),
+            "--writable".to_string(),
+        ]
+        .into_iter()
+        .chain(flags.iter().map(|flag| flag.to_string()));
+        Config::from_args(args).unwrap()
+    }
+
+    /// Uploads one file called `filename` into the root.
+    fn upload(config: &Config, filename: &str, contents: &str) -> Result<usize, UploadError> {
+        let body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+             Content-Type: text/plain\r\n\r\n{}\r\n--XyZ--\r\n",
+            filename, contents
+        );
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n\
+             Content-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let request = Request::parse(&head).unwrap();
+        let mount = &config.mounts[0];
+        save(&mount.root, mount, &request, &mut body.as_bytes(), config)
+    }
+
+    #[test]
+    fn saves_an_ordinary_file() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        assert!(matches!(upload(&config, "notes.txt", "hello"), Ok(1)));
+        assert_eq!(
+            fs::read_to_string(root.path().join("notes.txt")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn keeps_only_the_last_path_component() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        assert!(matches!(upload(&config, "../../evil.txt", "x"), Ok(1)));
+        assert!(root.path().join("evil.txt").is_file());
+    }
+
+    #[test]
+    fn refuses_dotfiles() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        assert!(matches!(
+            upload(&config, ".bashrc", "x"),
+            Err(UploadError::Hidden(_))
+        ));
+        assert!(!root.path().join(".bashrc").exists());
+    }
+
+    #[test]
+    fn refuses_the_ignore_file_even_with_show_hidden() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &["--show-hidden"]);
+        assert!(matches!(
+            upload(&config, ".serverignore", "*"),
+            Err(UploadError::Hidden(_))
+        ));
+        assert!(!root.path().join(".serverignore").exists());
+    }
+
+    #[test]
+    fn refuses_ignored_names() {
+        let root = TempDir::new("upload");
+        root.write(".serverignore", "*.log\n");
+        let config = config(root.path(), &[]);
+        assert!(matches!(
+            upload(&config, "debug.log", "x"),
+            Err(UploadError::Hidden(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_blocklisted_names() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        assert!(matches!(
+            upload(&config, "id_rsa", "x"),
+            Err(UploadError::Hidden(_))
+        ));
+        assert!(!root.path().join("id_rsa").exists());
+    }
+
+    #[test]
+    fn refuses_to_overwrite_hidden_files() {
+        let root = TempDir::new("upload");
+        root.write(".env", "SECRET=1");
+        let config = config(root.path(), &["--overwrite", "--show-hidden"]);
+        assert!(matches!(
+            upload(&config, ".env", "SECRET=2"),
+            Err(UploadError::Hidden(_))
+        ));
+        assert_eq!(
+            fs::read_to_string(root.path().join(".env")).unwrap(),
+            "SECRET=1"
+        );
+    }
+
+    #[test]
+    fn refuses_existing_files_without_overwrite() {
+        let root = TempDir::new("upload");
+        root.write("notes.txt", "old");
+        let config = config(root.path(), &[]);
+        assert!(matches!(
+            upload(&config, "notes.txt", "new"),
+            Err(UploadError::Exists(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_to_write_through_symlinks() {
+        let root = TempDir::new("upload");
+        let outside = TempDir::new("outside");
+        outside.write("target.txt", "keep");
+        std::os::unix::fs::symlink(
+            outside.path().join("target.txt"),
+            root.path().join("link.txt"),
+        )
+        .unwrap();
+        let config = config(root.path(), &["--overwrite"]);
+        assert!(matches!(
+            upload(&config, "link.txt", "new"),
+            Err(UploadError::Symlink(_))
+        ));
+        assert_eq!(
+            fs::read_to_string(outside.path().join("target.txt")).unwrap(),
+            "keep"
+        );
+    }
+
+    #[test]
+    fn multipart_boundaries() {
+        assert_eq!(
+            multipart_boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            multipart_boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(multipart_boundary("multipart/form-data"), None);
+        assert_eq!(multipart_boundary("multipart/form-data; boundary="), None);
+        assert_eq!(multipart_boundary("text/plain; boundary=abc"), None);
+    }
+
+    #[test]
+    fn a_body_that_ends_early_is_malformed() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nhalf";
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n\
+             Content-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let request = Request::parse(&head).unwrap();
+        let mount = &config.mounts[0];
+        let saved = save(&mount.root, mount, &request, &mut body.as_bytes(), &config);
+        assert!(matches!(saved, Err(UploadError::Malformed(_))));
+        assert!(!root.path().join("a.txt").exists());
+    }
+
+    /// A body that hands over one byte per read, so every delimiter is
+    /// split across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    /// Uploads a whole multipart `body` with the boundary `XyZ`, either all
+    /// at once or a byte at a time.
+    fn upload_body(config: &Config, body: &str, trickle: bool) -> Result<usize, UploadError> {
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n\
+             Content-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let request = Request::parse(&head).unwrap();
+        let mount = &config.mounts[0];
+        if trickle {
+            save(
+                &mount.root,
+                mount,
+                &request,
+                &mut Trickle(body.as_bytes()),
+                config,
+            )
+        } else {
+            save(&mount.root, mount, &request, &mut body.as_bytes(), config)
+        }
+    }
+
+    const SEVERAL_PARTS: &str = "preamble to skip\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"comment\"\r\n\r\n\
+        not a file\r\n\
+        --XyZ\r\n\
+        content-disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n\r\n\
+        first --XyZ inline\r\n-XyZ\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\r\n\
+        \r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n\
+        \r\n\r\nsecond\r\n\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    #[test]
+    fn saves_every_file_part() {
+        for trickle in [false, true] {
+            let root = TempDir::new("upload");
+            let config = config(root.path(), &[]);
+            assert!(matches!(
+                upload_body(&config, SEVERAL_PARTS, trickle),
+                Ok(2)
+            ));
+            assert_eq!(
+                fs::read_to_string(root.path().join("a.txt")).unwrap(),
+                "first --XyZ inline\r\n-XyZ"
+            );
+            assert_eq!(
+                fs::read_to_string(root.path().join("b.txt")).unwrap(),
+                "\r\n\r\nsecond\r\n"
+            );
+            let names: Vec<_> = fs::read_dir(root.path()).unwrap().collect();
+            assert_eq!(names.len(), 2);
+        }
+    }
+
+    #[test]
+    fn a_body_without_files_is_malformed() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        let body =
+            "--XyZ\r\nContent-Disposition: form-data; name=\"comment\"\r\n\r\nhi\r\n--XyZ--\r\n";
+        assert!(matches!(
+            upload_body(&config, body, false),
+            Err(UploadError::Malformed(_))
+        ));
+        assert!(matches!(
+            upload_body(&config, "no delimiter at all", false),
+            Err(UploadError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn a_broken_part_removes_only_its_own_file() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        let body = "--XyZ\r\nContent-Disposition: form-data; filename=\"a.txt\"\r\n\r\nfirst\r\n\
+                    --XyZ\r\nContent-Disposition: form-data; filename=\"b.txt\"\r\n\r\ncut off";
+        assert!(matches!(
+            upload_body(&config, body, true),
+            Err(UploadError::Malformed(_))
+        ));
+        assert!(root.path().join("a.txt").is_file());
+        assert!(!root.path().join("b.txt").exists());
+    }
+
+    #[test]
+    fn oversized_part_headers_are_malformed() {
+        let root = TempDir::new("upload");
+        let config = config(root.path(), &[]);
+        let body = format!(
+            "--XyZ\r\nX-Padding: {}\r\n\r\n",
+            "x".repeat(MAX_PART_HEADER_SIZE + 1)
+        );
+        assert!(matches!(
+            upload_body(&config, &body, false),
+            Err(UploadError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn file_names() {
+        let filename = |value| disposition_filename(value);
+        assert_eq!(
+            filename("form-data; name=\"f\"; filename=\"a b.txt\"").as_deref(),
+            Some("a b.txt")
+        );
+        assert_eq!(
+            filename("form-data; FILENAME=a.txt").as_deref(),
+            Some("a.txt")
+        );
+        assert_eq!(filename("form-data; name=\"filename\""), None);
+        assert_eq!(
+            safe_filename("C:\\Users\\me\\a.txt").ok().as_deref(),
+            Some("a.txt")
+        );
+        assert_eq!(safe_filename(" dir/a.txt ").ok().as_deref(), Some("a.txt"));
+        for bad in ["dir/", "..", "a/.", "a\0b"] {
+            assert!(
+                matches!(safe_filename(bad), Err(UploadError::Malformed(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::listing;
+use crate::logger;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// Most files looked at in one scan, so a huge tree can't keep the watcher
+/// busy forever. Changes beyond this go unnoticed.
+const MAX_WATCHED: usize = 100_000;
+
+/// The modification time and size of every file under the mounts.
+type Snapshot = HashMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Starts a thread that scans the mounts for files being added, changed and
+/// removed, logging each change and dropping changed files from the cache.
+/// There are no filesystem notifications to rely on, so every scan looks at
+/// every file again, once each `--watch-interval`.
+pub fn spawn(config: Arc<Config>) {
+    thread::spawn(move || {
+        let mut known = snapshot(&config);
+        logger::info(&format!(
+            "Watching for changes, {} files so far",
+            known.len()
+        ));
+        loop {
+            thread::sleep(config.watch_interval);
+            let current = snapshot(&config);
+            report(&known, &current, &config);
+            known = current;
+        }
+    });
+}
+
+fn snapshot(config: &Config) -> Snapshot {
+    config
+        .mounts
+        .iter()
+        .flat_map(|mount| {
+            WalkDir::new(&mount.root)
+                .min_depth(1)
+                .into_iter()
+                .filter_entry(|entry| !listing::is_hidden(entry, mount, config))
+                .flatten()
+        })
+        .filter_map(|entry| {
+            let metadata = fs::metadata(entry.path()).ok()?;
+            metadata.is_file().then(|| {
+                let stamp = (metadata.modified().ok(), metadata.len());
+                (entry.into_path(), stamp)
+            })
+        })
+        .take(MAX_WATCHED)
+        .collect()
+}
+
+/// Logs the differences between two snapshots. Files that changed or went
+/// away are dropped from the cache, so stale contents are never served.
+fn report(before: &Snapshot, after: &Snapshot, config: &Config) {
+    for (path, stamp) in after {
+        match before.get(path) {
+            None => logger::info(&format!("Added {}", path.display())),
+            Some(previous) if previous != stamp => {
+                logger::info(&format!("Modified {}", path.display()));
+                if let Some(cache) = &config.cache {
+                    cache.remove(path);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    for path in before.keys().filter(|path| !after.contains_key(*path)) {
+        logger::info(&format!("Removed {}", path.display()));
+        if let Some(cache) = &config.cache {
+            cache.remove(path);
+        }
+    }
+}